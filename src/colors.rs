@@ -1,5 +1,7 @@
 use termcolor::{Color, ColorSpec};
 
+use crate::diff::{FileStatus, ParentChange};
+
 pub struct DarkTheme;
 
 impl DarkTheme {
@@ -23,12 +25,130 @@ impl DarkTheme {
         spec
     }
     
+    pub fn added_emphasis() -> ColorSpec {
+        let mut spec = ColorSpec::new();
+        spec.set_bg(Some(Color::Rgb(0, 96, 0))); // Brighter green for changed words
+        spec
+    }
+    
+    pub fn removed_emphasis() -> ColorSpec {
+        let mut spec = ColorSpec::new();
+        spec.set_bg(Some(Color::Rgb(112, 0, 0))); // Brighter red for changed words
+        spec
+    }
+    
+    /// Marker column of a combined diff line, drawn over the line's own background
+    pub fn parent_marker(change: &ParentChange, base: &ColorSpec) -> ColorSpec {
+        let mut spec = base.clone();
+        match change {
+            ParentChange::Added => spec.set_fg(Some(Color::Green)).set_bold(true).set_intense(true),
+            ParentChange::Removed => spec.set_fg(Some(Color::Red)).set_bold(true).set_intense(true),
+            ParentChange::Unchanged => &mut spec,
+        };
+        spec
+    }
+    
+    /// Background of removed lines that reappear elsewhere, `alternate` for every other block
+    pub fn moved_removed(alternate: bool) -> ColorSpec {
+        let mut spec = ColorSpec::new();
+        let background = if alternate { Color::Rgb(72, 16, 72) } else { Color::Rgb(48, 0, 48) }; // Purple
+        spec.set_bg(Some(background));
+        spec
+    }
+    
+    /// Background of added lines that were moved from elsewhere, `alternate` for every other block
+    pub fn moved_added(alternate: bool) -> ColorSpec {
+        let mut spec = ColorSpec::new();
+        let background = if alternate { Color::Rgb(16, 64, 72) } else { Color::Rgb(0, 40, 48) }; // Teal
+        spec.set_bg(Some(background));
+        spec
+    }
+    
     pub fn context_line() -> ColorSpec {
         let mut spec = ColorSpec::new();
         spec.set_fg(Some(Color::White));
         spec
     }
     
+    pub fn column_separator() -> ColorSpec {
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::White))
+            .set_dimmed(true);
+        spec
+    }
+    
+    pub fn line_number() -> ColorSpec {
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::White))
+            .set_dimmed(true);
+        spec
+    }
+    
+    pub fn search_match() -> ColorSpec {
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::Black))
+            .set_bg(Some(Color::Yellow));
+        spec
+    }
+    
+    pub fn file_status(status: &FileStatus) -> ColorSpec {
+        let color = match status {
+            FileStatus::Added => Color::Green,
+            FileStatus::Deleted => Color::Red,
+            FileStatus::Renamed | FileStatus::Copied => Color::Yellow,
+            FileStatus::Modified | FileStatus::ModeChanged => Color::Blue,
+        };
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(color))
+            .set_bold(true)
+            .set_intense(true);
+        spec
+    }
+    
+    pub fn commit_sha() -> ColorSpec {
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::Yellow))
+            .set_bold(true)
+            .set_intense(true);
+        spec
+    }
+    
+    pub fn commit_key() -> ColorSpec {
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::White))
+            .set_bold(true);
+        spec
+    }
+    
+    pub fn commit_message() -> ColorSpec {
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::White))
+            .set_dimmed(true);
+        spec
+    }
+    
+    pub fn binary_summary() -> ColorSpec {
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::Magenta))
+            .set_bold(true);
+        spec
+    }
+    
+    pub fn encoding_note() -> ColorSpec {
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::Yellow))
+            .set_italic(true);
+        spec
+    }
+    
+    pub fn no_newline() -> ColorSpec {
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::White))
+            .set_dimmed(true)
+            .set_italic(true);
+        spec
+    }
+    
     pub fn hunk_header() -> ColorSpec {
         let mut spec = ColorSpec::new();
         spec.set_fg(Some(Color::Cyan))
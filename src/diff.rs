@@ -1,205 +1,1769 @@
-use similar::{ChangeTag, TextDiff};
+use std::borrow::{Borrow, Cow};
+use std::collections::HashMap;
+use std::ops::Range;
+use similar::{capture_diff_slices, Algorithm, ChangeTag, DiffOp, DiffTag, TextDiff};
 use anyhow::Result;
 
+use crate::patch::{FileDiff, Hunk, HunkRange, Patch, PatchBuilder};
 use crate::syntax::{SyntaxHighlighter, SyntaxType};
 
+/// One rendered line of a diff, with everything the pager needs to draw it
 #[derive(Debug, Clone)]
 pub struct DiffLine {
     pub line_type: DiffLineType,
+    /// Text of the line without its `+`/`-`/` ` marker
     pub content: String,
+    /// `content` split into spans by syntax type
     pub syntax_highlights: Vec<(SyntaxType, String)>,
+    /// Byte ranges of `content` that differ from the paired removed/added line
+    pub emphasis: Vec<Range<usize>>,
+    /// 1-based line numbers in the old and new file, for lines that exist on that side
+    pub old_line_no: Option<usize>,
+    pub new_line_no: Option<usize>,
+    /// Per-parent markers of a line in a combined (merge) diff, empty for ordinary diffs
+    pub parents: Vec<ParentChange>,
+    /// Number of the moved block a removed or added line belongs to, see `mark_moved_lines`
+    pub moved: Option<usize>,
 }
 
+/// How a line of a combined diff (`diff --cc`) compares against one merge parent
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum ParentChange {
+    /// ` `: the parent agrees with the merge result about this line
+    Unchanged,
+    /// `+`: the line is in the merge result but not in this parent
+    Added,
+    /// `-`: the line is in this parent but not in the merge result
+    Removed,
+}
+
+impl ParentChange {
+    fn from_marker(marker: char) -> Option<Self> {
+        match marker {
+            ' ' => Some(ParentChange::Unchanged),
+            '+' => Some(ParentChange::Added),
+            '-' => Some(ParentChange::Removed),
+            _ => None,
+        }
+    }
+    
+    /// The marker character of this parent's column
+    pub fn marker(&self) -> char {
+        match self {
+            ParentChange::Unchanged => ' ',
+            ParentChange::Added => '+',
+            ParentChange::Removed => '-',
+        }
+    }
+}
+
+/// What a `DiffLine` is, which decides how it is drawn
 #[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
 pub enum DiffLineType {
     Added,
     Removed,
     Context,
+    /// `---`/`+++` line of a file without a banner
     FileHeader,
+    /// `@@ -a,b +c,d @@` line starting a hunk
     HunkHeader,
+    /// `commit <sha>` line starting a commit in `git log -p` or `git show` output
+    CommitHeader,
+    /// `Author:`, `Date:` and similar lines of a commit
+    CommitMeta,
+    /// Indented commit message line
+    CommitMessage,
+    /// `\ No newline at end of file` after the last line of a file that lacks one
+    NoNewline,
+    /// `Binary files a and b differ (1.2 KiB → 1.5 KiB)` in place of the hunks of a binary file
+    BinarySummary,
+    /// `Encoding: Shift_JIS → UTF-8` when the two sides were decoded from different encodings
+    EncodingNote,
+    /// Summary of a file's git extended header (`diff --git`, `new file mode`, `rename from`, ...)
+    FileBanner(FileMeta),
 }
 
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum FileStatus {
+    Modified,
+    Added,
+    Deleted,
+    Renamed,
+    Copied,
+    ModeChanged,
+}
+
+impl FileStatus {
+    /// Lower-case description, as shown in banners and `--brief` output
+    pub fn label(&self) -> &'static str {
+        match self {
+            FileStatus::Modified => "modified",
+            FileStatus::Added => "added",
+            FileStatus::Deleted => "deleted",
+            FileStatus::Renamed => "renamed",
+            FileStatus::Copied => "copied",
+            FileStatus::ModeChanged => "mode changed",
+        }
+    }
+}
+
+/// File-level metadata collected from the lines between `diff --git` and the first hunk
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileMeta {
+    pub old_path: String,
+    pub new_path: String,
+    pub status: FileStatus,
+    pub old_mode: Option<String>,
+    pub new_mode: Option<String>,
+    /// Similarity percentage of a rename or copy
+    pub similarity: Option<u8>,
+    pub binary: bool,
+}
+
+impl FileMeta {
+    /// A modified file, until extended header lines say otherwise
+    pub fn new(old_path: &str, new_path: &str) -> Self {
+        Self {
+            old_path: old_path.to_string(),
+            new_path: new_path.to_string(),
+            status: FileStatus::Modified,
+            old_mode: None,
+            new_mode: None,
+            similarity: None,
+            binary: false,
+        }
+    }
+    
+    /// Parses the `a/old b/new` part of a `diff --git` line
+    fn from_git_header(paths: &str) -> Self {
+        let (old_path, new_path) = split_git_paths(paths);
+        Self::new(old_path, new_path)
+    }
+    
+    /// Parses `Binary files a/x and b/y differ`
+    fn from_binary_notice(line: &str) -> Option<Self> {
+        let paths = line.strip_prefix("Binary files ")?.strip_suffix(" differ")?;
+        let (old_path, new_path) = paths.split_once(" and ")?;
+        let mut meta = Self::new(strip_path_prefix(old_path, "a/"), strip_path_prefix(new_path, "b/"));
+        meta.binary = true;
+        meta.apply_dev_null();
+        Some(meta)
+    }
+    
+    /// Folds one extended header line into the metadata, returning whether it was recognized
+    fn apply_extended_header(&mut self, line: &str) -> bool {
+        if let Some(mode) = line.strip_prefix("new file mode ") {
+            self.status = FileStatus::Added;
+            self.new_mode = Some(mode.to_string());
+        } else if let Some(mode) = line.strip_prefix("deleted file mode ") {
+            self.status = FileStatus::Deleted;
+            self.old_mode = Some(mode.to_string());
+        } else if let Some(mode) = line.strip_prefix("old mode ") {
+            self.old_mode = Some(mode.to_string());
+        } else if let Some(mode) = line.strip_prefix("new mode ") {
+            self.new_mode = Some(mode.to_string());
+            if self.status == FileStatus::Modified {
+                self.status = FileStatus::ModeChanged;
+            }
+        } else if let Some(path) = line.strip_prefix("rename from ") {
+            self.status = FileStatus::Renamed;
+            self.old_path = path.to_string();
+        } else if let Some(path) = line.strip_prefix("rename to ") {
+            self.status = FileStatus::Renamed;
+            self.new_path = path.to_string();
+        } else if let Some(path) = line.strip_prefix("copy from ") {
+            self.status = FileStatus::Copied;
+            self.old_path = path.to_string();
+        } else if let Some(path) = line.strip_prefix("copy to ") {
+            self.status = FileStatus::Copied;
+            self.new_path = path.to_string();
+        } else if let Some(percent) = line.strip_prefix("similarity index ").or_else(|| line.strip_prefix("dissimilarity index ")) {
+            self.similarity = percent.trim_end_matches('%').parse().ok();
+        } else if let Some(modes) = line.strip_prefix("mode ") {
+            // Combined diffs list the parent modes first: `mode 100644,100644..100755`
+            if let Some((old_modes, new_mode)) = modes.split_once("..") {
+                self.old_mode = old_modes.split(',').next().map(str::to_string);
+                self.new_mode = Some(new_mode.to_string());
+            }
+        } else if let Some(index) = line.strip_prefix("index ") {
+            // `index abc123..def456 100644` carries the mode when it did not change
+            if let Some((_, mode)) = index.split_once(' ') {
+                self.old_mode.get_or_insert_with(|| mode.to_string());
+                self.new_mode.get_or_insert_with(|| mode.to_string());
+            }
+        } else if line.starts_with("Binary files ") || line == "GIT binary patch" {
+            self.binary = true;
+        } else if let Some(path) = line.strip_prefix("--- ") {
+            // Unlike the `diff --git` line these name a single path, so they are exact
+            match strip_path_prefix(path, "a/") {
+                "/dev/null" => self.status = FileStatus::Added,
+                path => self.old_path = path.to_string(),
+            }
+        } else if let Some(path) = line.strip_prefix("+++ ") {
+            match strip_path_prefix(path, "b/") {
+                "/dev/null" => self.status = FileStatus::Deleted,
+                path => self.new_path = path.to_string(),
+            }
+        } else {
+            return false;
+        }
+        true
+    }
+    
+    fn apply_dev_null(&mut self) {
+        if self.old_path == "/dev/null" {
+            self.status = FileStatus::Added;
+        } else if self.new_path == "/dev/null" {
+            self.status = FileStatus::Deleted;
+        }
+    }
+    
+    /// One-line description such as `renamed: src/a.rs → src/util/a.rs (92%)`
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{}: ", self.status.label());
+        match self.status {
+            FileStatus::Renamed | FileStatus::Copied => {
+                summary.push_str(&format!("{} → {}", self.old_path, self.new_path));
+                if let Some(similarity) = self.similarity {
+                    summary.push_str(&format!(" ({}%)", similarity));
+                }
+            },
+            FileStatus::Deleted => summary.push_str(&self.old_path),
+            _ => summary.push_str(&self.new_path),
+        }
+        
+        if let (Some(old_mode), Some(new_mode)) = (&self.old_mode, &self.new_mode) {
+            if old_mode != new_mode {
+                summary.push_str(&format!(" [{} → {}]", old_mode, new_mode));
+            }
+        }
+        if self.binary {
+            summary.push_str(" (binary)");
+        }
+        summary
+    }
+}
+
+/// Splits the `a/old b/new` part of a `diff --git` line into the two paths without prefixes.
+/// Git doesn't quote paths with spaces, so a path containing ` b/` is only split right when
+/// both paths are the same; the `---`/`+++` lines correct the others.
+fn split_git_paths(paths: &str) -> (&str, &str) {
+    let paths = paths.trim();
+    let middle = paths.len() / 2;
+    if paths.len() % 2 == 1 && paths.is_char_boundary(middle) && paths[middle..].starts_with(' ') {
+        let (old_path, new_path) = (strip_path_prefix(&paths[..middle], "a/"), strip_path_prefix(&paths[middle + 1..], "b/"));
+        if old_path == new_path {
+            return (old_path, new_path);
+        }
+    }
+    
+    let (old_path, new_path) = match paths.find(" b/").or_else(|| paths.find(" \"b/")) {
+        Some(split) => (&paths[..split], &paths[split + 1..]),
+        None => paths.split_once(' ').unwrap_or((paths, paths)),
+    };
+    (strip_path_prefix(old_path, "a/"), strip_path_prefix(new_path, "b/"))
+}
+
+/// Strips the `a/`/`b/` prefix git puts on paths, along with quotes and the timestamp
+/// `diff -u` appends after a tab
+fn strip_path_prefix<'a>(path: &'a str, prefix: &str) -> &'a str {
+    let path = path.split('\t').next().unwrap_or(path).trim_matches('"');
+    path.strip_prefix(prefix).unwrap_or(path)
+}
+
+/// Line diff algorithm `generate_diff` compares texts with
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[non_exhaustive]
+pub enum DiffAlgorithm {
+    #[default]
+    Myers,
+    Patience,
+    Lcs,
+}
+
+impl From<DiffAlgorithm> for Algorithm {
+    fn from(algorithm: DiffAlgorithm) -> Self {
+        match algorithm {
+            DiffAlgorithm::Myers => Algorithm::Myers,
+            DiffAlgorithm::Patience => Algorithm::Patience,
+            DiffAlgorithm::Lcs => Algorithm::Lcs,
+        }
+    }
+}
+
+/// Whitespace differences that `generate_diff` disregards when comparing lines. Lines that
+/// compare equal are still shown as written in the old version.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct WhitespaceOptions {
+    /// `-w`: ignore all whitespace
+    pub ignore_all: bool,
+    /// `-b`: ignore changes in the amount of whitespace, including trailing whitespace
+    pub ignore_amount: bool,
+    /// Drop hunks whose changes are all blank lines
+    pub ignore_blank_lines: bool,
+    /// Treat CRLF and LF line endings alike
+    pub ignore_eol: bool,
+}
+
+impl WhitespaceOptions {
+    /// The key a line is compared by. The line ending is kept so a missing final newline
+    /// still shows up as a change.
+    fn normalize<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        if !self.ignore_all && !self.ignore_amount && !self.ignore_eol {
+            return Cow::Borrowed(line);
+        }
+        
+        let (body, newline) = match line.strip_suffix('\n') {
+            Some(body) => (body, "\n"),
+            None => (line, ""),
+        };
+        let body = if self.ignore_eol { body.strip_suffix('\r').unwrap_or(body) } else { body };
+        
+        let body = if self.ignore_all {
+            body.chars().filter(|ch| !ch.is_whitespace()).collect::<String>()
+        } else if self.ignore_amount {
+            // Every run of whitespace counts as one space, trailing whitespace as none
+            let mut collapsed = String::with_capacity(body.len());
+            for ch in body.trim_end().chars() {
+                if !ch.is_whitespace() {
+                    collapsed.push(ch);
+                } else if !collapsed.ends_with(' ') {
+                    collapsed.push(' ');
+                }
+            }
+            collapsed
+        } else {
+            body.to_string()
+        };
+        Cow::Owned(body + newline)
+    }
+}
+
+/// Options of a single `DiffProcessor::generate_diff` call. Options that apply to every
+/// comparison, such as the algorithm, are set on the processor instead.
+#[derive(Debug, Clone, Default)]
+pub struct DiffOptions {
+    old_name: Option<String>,
+    new_name: Option<String>,
+    language: Option<String>,
+}
+
+impl DiffOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Name shown in the `---` header, `a` by default.
+    pub fn old_name(mut self, name: impl Into<String>) -> Self {
+        self.old_name = Some(name.into());
+        self
+    }
+    
+    /// Name shown in the `+++` header, `b` by default.
+    pub fn new_name(mut self, name: impl Into<String>) -> Self {
+        self.new_name = Some(name.into());
+        self
+    }
+    
+    /// Language to highlight with, e.g. `rust`. Detected from the file names by default.
+    pub fn language(mut self, language: impl Into<String>) -> Self {
+        self.language = Some(language.into());
+        self
+    }
+}
+
+/// Produces `DiffLine`s, either by comparing two texts or by parsing an existing diff.
+///
+/// A processor is `Send` but not `Sync`: its `SyntaxHighlighter` reuses one parser through
+/// a `RefCell`. Give each thread its own processor.
 pub struct DiffProcessor {
     syntax_highlighter: SyntaxHighlighter,
+    algorithm: DiffAlgorithm,
+    context_lines: usize,
+    function_context: bool,
+    whitespace: WhitespaceOptions,
+}
+
+impl Default for DiffProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DiffProcessor {
     pub fn new() -> Self {
         Self {
-            syntax_highlighter: SyntaxHighlighter::new().unwrap_or_else(|_| {
-                eprintln!("Warning: Failed to initialize syntax highlighter");
-                SyntaxHighlighter::new().unwrap()
-            }),
+            syntax_highlighter: SyntaxHighlighter::new(),
+            algorithm: DiffAlgorithm::Myers,
+            context_lines: 3,
+            function_context: false,
+            whitespace: WhitespaceOptions::default(),
         }
     }
     
-    pub fn generate_diff(&self, old_content: &str, new_content: &str, old_filename: Option<&str>, new_filename: Option<&str>) -> Result<Vec<DiffLine>> {
-        let mut result = Vec::new();
+    /// Line diff algorithm used when comparing two texts.
+    pub fn algorithm(mut self, algorithm: DiffAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+    
+    /// Number of unchanged lines shown around each change.
+    pub fn context_lines(mut self, lines: usize) -> Self {
+        self.context_lines = lines;
+        self
+    }
+    
+    /// Widen the context of each change to the whole function containing it, for languages
+    /// with a grammar.
+    pub fn function_context(mut self, enabled: bool) -> Self {
+        self.function_context = enabled;
+        self
+    }
+    
+    /// Whitespace differences to disregard when comparing two texts.
+    pub fn whitespace(mut self, options: WhitespaceOptions) -> Self {
+        self.whitespace = options;
+        self
+    }
+    
+    /// Whether two texts differ once the whitespace options are applied
+    pub fn differs(&self, old_content: &str, new_content: &str) -> bool {
+        if old_content == new_content {
+            return false;
+        }
+        let (old_lines, new_lines) = (split_lines(old_content), split_lines(new_content));
+        self.line_ops(&old_lines, &new_lines)
+            .iter()
+            .any(|op| op.tag() != DiffTag::Equal && !self.only_blank_lines(op, &old_lines, &new_lines))
+    }
+    
+    fn line_ops(&self, old_lines: &[&str], new_lines: &[&str]) -> Vec<DiffOp> {
+        let old_keys = old_lines.iter().map(|line| self.whitespace.normalize(line)).collect::<Vec<_>>();
+        let new_keys = new_lines.iter().map(|line| self.whitespace.normalize(line)).collect::<Vec<_>>();
+        capture_diff_slices(self.algorithm.into(), &old_keys, &new_keys)
+    }
+    
+    /// Whether `op` only adds or removes blank lines that `--ignore-blank-lines` disregards
+    fn only_blank_lines(&self, op: &DiffOp, old_lines: &[&str], new_lines: &[&str]) -> bool {
+        self.whitespace.ignore_blank_lines
+            && old_lines[op.old_range()].iter().chain(&new_lines[op.new_range()]).all(|line| line.trim().is_empty())
+    }
+    
+    /// Diffs two complete texts into a single file with `---`/`+++` headers followed by hunks,
+    /// with syntax highlighting and word-level emphasis. `options` names the two sides.
+    pub fn generate_diff(&self, old_content: &str, new_content: &str, options: &DiffOptions) -> Result<Patch> {
+        let old_name = options.old_name.as_deref().unwrap_or("a");
+        let new_name = options.new_name.as_deref().unwrap_or("b");
+        let mut file = FileDiff::new(FileMeta::new(old_name, new_name));
+        file.header.push(plain_line(DiffLineType::FileHeader, &format!("--- {}", old_name)));
+        file.header.push(plain_line(DiffLineType::FileHeader, &format!("+++ {}", new_name)));
         
-        let old_name = old_filename.unwrap_or("a");
-        let new_name = new_filename.unwrap_or("b");
+        let language = match &options.language {
+            Some(language) => Some(language.as_str()),
+            None => self.syntax_highlighter.detect_language(options.old_name.as_deref().or(options.new_name.as_deref())),
+        };
+        file.hunks = self.hunks(old_content, new_content, language);
         
-        result.push(DiffLine {
-            line_type: DiffLineType::FileHeader,
-            content: format!("--- {}", old_name),
-            syntax_highlights: vec![(SyntaxType::Normal, format!("--- {}", old_name))],
-        });
+        Ok(Patch::from_files(vec![file]))
+    }
+    
+    /// Diffs one file of a directory comparison: a banner naming the file and its status,
+    /// followed by the hunks
+    pub fn generate_file_diff(&self, meta: &FileMeta, old_content: &str, new_content: &str) -> Result<FileDiff> {
+        let mut file = FileDiff::new(meta.clone());
+        file.header.push(banner_line(meta));
         
-        result.push(DiffLine {
-            line_type: DiffLineType::FileHeader,
-            content: format!("+++ {}", new_name),
-            syntax_highlights: vec![(SyntaxType::Normal, format!("+++ {}", new_name))],
-        });
+        let path = if meta.status == FileStatus::Deleted { &meta.old_path } else { &meta.new_path };
+        file.hunks = self.hunks(old_content, new_content, self.path_language(path));
         
-        let diff = TextDiff::from_lines(old_content, new_content);
-        let language = self.syntax_highlighter.detect_language(old_filename.or(new_filename));
+        Ok(file)
+    }
+    
+    /// The hunks of a line diff between two complete texts, with word-level emphasis
+    fn hunks(&self, old_content: &str, new_content: &str, language: Option<&str>) -> Vec<Hunk> {
+        let (old_lines, new_lines) = (split_lines(old_content), split_lines(new_content));
+        let ops = self.line_ops(&old_lines, &new_lines);
+        let old_highlights = self.syntax_highlighter.highlight_document(old_content, language).unwrap_or_default();
+        let new_highlights = self.syntax_highlighter.highlight_document(new_content, language).unwrap_or_default();
         
-        for (group_idx, group) in diff.grouped_ops(3).iter().enumerate() {
-            if group_idx > 0 {
-                result.push(DiffLine {
-                    line_type: DiffLineType::Context,
-                    content: String::new(),
-                    syntax_highlights: vec![(SyntaxType::Normal, String::new())],
-                });
-            }
-            
+        let (old_functions, new_functions) = if self.function_context {
+            (
+                self.syntax_highlighter.function_ranges(old_content, language).unwrap_or_default(),
+                self.syntax_highlighter.function_ranges(new_content, language).unwrap_or_default(),
+            )
+        } else {
+            (Vec::new(), Vec::new())
+        };
+        let context = HunkContext { lines: self.context_lines, old_functions, new_functions };
+        
+        let mut groups = context.group_ops(&ops);
+        groups.retain(|group| group.iter().any(|op| op.tag() != DiffTag::Equal && !self.only_blank_lines(op, &old_lines, &new_lines)));
+        
+        let mut hunks = Vec::new();
+        for group in &groups {
             let first_op = &group[0];
             let last_op = &group[group.len() - 1];
             
-            let old_start = first_op.old_range().start + 1;
-            let old_end = last_op.old_range().end;
-            let new_start = first_op.new_range().start + 1;
-            let new_end = last_op.new_range().end;
-            
-            let hunk_header = format!("@@ -{},{} +{},{} @@", old_start, old_end - old_start + 1, new_start, new_end - new_start + 1);
-            
-            result.push(DiffLine {
-                line_type: DiffLineType::HunkHeader,
-                content: hunk_header.clone(),
-                syntax_highlights: vec![(SyntaxType::Normal, hunk_header)],
-            });
-            
-            let mut _old_line_no = old_start;
-            let mut _new_line_no = new_start;
+            let old_range = hunk_range(first_op.old_range().start..last_op.old_range().end);
+            let new_range = hunk_range(first_op.new_range().start..last_op.new_range().end);
+            let header = plain_line(DiffLineType::HunkHeader, &format!("@@ -{} +{} @@", old_range, new_range));
+            let mut lines = Vec::new();
             
             for op in group {
-                for change in diff.iter_changes(op) {
+                for change in op.iter_changes(&old_lines, &new_lines) {
                     let line_type = match change.tag() {
-                        ChangeTag::Delete => {
-                            _old_line_no += 1;
-                            DiffLineType::Removed
-                        },
-                        ChangeTag::Insert => {
-                            _new_line_no += 1;
-                            DiffLineType::Added
-                        },
-                        ChangeTag::Equal => {
-                            _old_line_no += 1;
-                            _new_line_no += 1;
-                            DiffLineType::Context
-                        },
+                        ChangeTag::Delete => DiffLineType::Removed,
+                        ChangeTag::Insert => DiffLineType::Added,
+                        ChangeTag::Equal => DiffLineType::Context,
                     };
                     
                     let line_content = change.value().trim_end_matches('\n').to_string();
-                    let syntax_highlights = self.syntax_highlighter
-                        .highlight_line(&line_content, language)
-                        .unwrap_or_else(|_| vec![(SyntaxType::Normal, line_content.clone())]);
+                    // Added lines take their colours from the new version, everything else from the old one
+                    let document_line = match change.tag() {
+                        ChangeTag::Insert => change.new_index().and_then(|idx| new_highlights.get(idx)),
+                        _ => change.old_index().and_then(|idx| old_highlights.get(idx)),
+                    };
+                    let syntax_highlights = document_line
+                        .cloned()
+                        .unwrap_or_else(|| vec![(SyntaxType::Normal, line_content.clone())]);
                     
-                    result.push(DiffLine {
+                    lines.push(DiffLine {
                         line_type,
                         content: line_content,
                         syntax_highlights,
+                        emphasis: Vec::new(),
+                        old_line_no: change.old_index().map(|idx| idx + 1),
+                        new_line_no: change.new_index().map(|idx| idx + 1),
+                        parents: Vec::new(),
+                        moved: None,
                     });
+                    
+                    if change.missing_newline() {
+                        lines.push(plain_line(DiffLineType::NoNewline, NO_NEWLINE_MARKER));
+                    }
                 }
             }
+            
+            emphasize_changes(&mut lines);
+            hunks.push(Hunk { old_ranges: vec![old_range], new_range, header, lines, trailer: Vec::new() });
         }
-        
-        Ok(result)
+        hunks
     }
     
-    pub fn parse_diff(&self, diff_content: &str) -> Result<Vec<DiffLine>> {
-        let mut result = Vec::new();
+    /// Parses unified diff text, such as `git diff`, `git log -p` or `diff -u` output, adding
+    /// syntax highlighting for the languages of the files it touches.
+    pub fn parse_diff(&self, diff_content: &str) -> Result<Patch> {
+        let mut patch = PatchBuilder::default();
         let mut current_language = None;
+        // Indices into the current hunk of the lines making up its old and new side
+        let mut hunk_old_side = Vec::new();
+        let mut hunk_new_side = Vec::new();
+        // Next line number on each side and how many lines the `@@` header says are left. A
+        // combined diff has one old side per merge parent.
+        let mut parent_lines = vec![HunkCursor::default()];
+        let mut new_line = HunkCursor::default();
+        // Whether the last `@@` header had counts; once they are used up, the lines that follow
+        // are not part of the hunk whatever they start with
+        let mut hunk_counts_known = false;
+        // Whether the current file has a banner whose extended header is still being read
+        let mut in_extended_header = false;
+        let mut in_binary_patch = false;
+        let mut commit_part: Option<CommitPart> = None;
         
         for line in diff_content.lines() {
-            if line.starts_with("--- ") {
-                let filename = line.strip_prefix("--- ").unwrap_or("");
-                if let Some(lang) = self.syntax_highlighter.detect_language(Some(filename)) {
-                    current_language = Some(lang);
+            // Inside a hunk the header counts tell us what is content, even for a removed
+            // line that happens to start with "-- "
+            let in_hunk = parent_lines.iter().any(|cursor| cursor.remaining > 0) || new_line.remaining > 0;
+            
+            // `git log -p` and `git show` put a commit section before the diffs of each commit
+            if !in_hunk {
+                let commit_line = if is_commit_start(line) {
+                    Some(DiffLineType::CommitHeader)
+                } else {
+                    match commit_part {
+                        Some(CommitPart::Header) if line.is_empty() => {
+                            commit_part = Some(CommitPart::Message);
+                            Some(DiffLineType::CommitMessage)
+                        },
+                        Some(CommitPart::Header) => Some(DiffLineType::CommitMeta),
+                        // Indented message lines, and the ` file | 2 +-` lines of `--stat` after their
+                        // `---` separator
+                        Some(CommitPart::Message) if line.is_empty() || line.starts_with(' ') || line == "---" => Some(DiffLineType::CommitMessage),
+                        _ => None,
+                    }
+                };
+                
+                if let Some(line_type) = commit_line {
+                    self.flush_hunk(&mut patch, &mut hunk_old_side, &mut hunk_new_side, current_language);
+                    if line_type == DiffLineType::CommitHeader {
+                        commit_part = Some(CommitPart::Header);
+                        in_extended_header = false;
+                        current_language = None;
+                        patch.start_commit(line.split_whitespace().nth(1).unwrap_or_default());
+                    }
+                    patch.push(plain_line(line_type, line));
+                    continue;
+                }
+                commit_part = None;
+            }
+            
+            // Belongs to the hunk line before it, without counting as a line of either side
+            if line.starts_with("\\ ") {
+                patch.push(plain_line(DiffLineType::NoNewline, line));
+                continue;
+            }
+            
+            let hunk_line = if !in_hunk && hunk_counts_known {
+                None
+            } else if parent_lines.len() > 1 {
+                // Combined diffs have no loose lines outside of hunks
+                if in_hunk { parse_combined_markers(line, parent_lines.len()) } else { None }
+            } else {
+                match line.chars().next() {
+                    Some('+') if in_hunk || !line.starts_with("+++ ") => Some((DiffLineType::Added, vec![ParentChange::Added])),
+                    Some('-') if in_hunk || !line.starts_with("--- ") => Some((DiffLineType::Removed, vec![ParentChange::Removed])),
+                    Some(' ') | None => Some((DiffLineType::Context, vec![ParentChange::Unchanged])),
+                    _ => None,
                 }
+            };
+            
+            if let Some((line_type, markers)) = hunk_line {
+                let content = line.chars().skip(markers.len()).collect::<String>();
+                let removed = line_type == DiffLineType::Removed;
                 
-                result.push(DiffLine {
-                    line_type: DiffLineType::FileHeader,
-                    content: line.to_string(),
-                    syntax_highlights: vec![(SyntaxType::Normal, line.to_string())],
-                });
-            } else if line.starts_with("+++ ") {
-                let filename = line.strip_prefix("+++ ").unwrap_or("");
-                if current_language.is_none() {
-                    if let Some(lang) = self.syntax_highlighter.detect_language(Some(filename)) {
-                        current_language = Some(lang);
+                // A removed line exists in the parents marked `-`, any other line in the parents
+                // not marked `+`. The gutter shows the line number in the first parent.
+                let mut old_line_no = None;
+                for (parent, (cursor, marker)) in parent_lines.iter_mut().zip(&markers).enumerate() {
+                    let in_parent = if removed { *marker == ParentChange::Removed } else { *marker != ParentChange::Added };
+                    if in_parent {
+                        let line_no = cursor.advance();
+                        if parent == 0 {
+                            old_line_no = line_no;
+                        }
                     }
                 }
+                let new_line_no = if removed { None } else { new_line.advance() };
                 
-                result.push(DiffLine {
-                    line_type: DiffLineType::FileHeader,
-                    content: line.to_string(),
-                    syntax_highlights: vec![(SyntaxType::Normal, line.to_string())],
+                // Highlighted once the whole hunk is known, see `highlight_hunk_side`
+                let index = patch.push(DiffLine {
+                    line_type,
+                    syntax_highlights: vec![(SyntaxType::Normal, content.clone())],
+                    emphasis: Vec::new(),
+                    old_line_no,
+                    new_line_no,
+                    parents: if parent_lines.len() > 1 { markers } else { Vec::new() },
+                    moved: None,
+                    content,
                 });
-            } else if line.starts_with("@@") {
                 
-                result.push(DiffLine {
-                    line_type: DiffLineType::HunkHeader,
-                    content: line.to_string(),
-                    syntax_highlights: vec![(SyntaxType::Normal, line.to_string())],
-                });
-            } else if line.starts_with('+') {
-                let content = line.chars().skip(1).collect::<String>();
-                let syntax_highlights = self.syntax_highlighter
-                    .highlight_line(&content, current_language)
-                    .unwrap_or_else(|_| vec![(SyntaxType::Normal, content.clone())]);
+                // Lines that landed in a header or trailer, outside the hunk, stay unhighlighted
+                if let Some(index) = index {
+                    if removed || old_line_no.is_some() {
+                        hunk_old_side.push(index);
+                    }
+                    if !removed {
+                        hunk_new_side.push(index);
+                    }
+                }
+                continue;
+            }
+            
+            self.flush_hunk(&mut patch, &mut hunk_old_side, &mut hunk_new_side, current_language);
+            
+            if let Some(paths) = line.strip_prefix("diff --git ") {
+                // Every file starts over; `---`/`+++` below refine this when present
+                current_language = self.path_language(split_git_paths(paths).1);
+                let meta = FileMeta::from_git_header(paths);
+                patch.start_file(meta.clone());
+                patch.push(banner_line(&meta));
+                in_extended_header = true;
+                in_binary_patch = false;
+                parent_lines = vec![HunkCursor::default()];
+                continue;
+            }
+            
+            if let Some(path) = line.strip_prefix("diff --cc ").or_else(|| line.strip_prefix("diff --combined ")) {
+                // Combined diffs of a merge name the file once, without `a/`/`b/` prefixes
+                current_language = self.path_language(path);
+                let meta = FileMeta::new(path, path);
+                patch.start_file(meta.clone());
+                patch.push(banner_line(&meta));
+                in_extended_header = true;
+                in_binary_patch = false;
+                continue;
+            }
+            
+            // The command line `diff -r` prints before each pair of files; `---`/`+++` name them
+            if line.starts_with("diff ") {
+                current_language = None;
+                patch.start_file(FileMeta::new("", ""));
+                patch.push(plain_line(DiffLineType::FileHeader, line));
+                in_extended_header = false;
+                in_binary_patch = false;
+                parent_lines = vec![HunkCursor::default()];
+                continue;
+            }
+            
+            if in_binary_patch {
+                continue;
+            }
+            
+            if in_extended_header {
+                // `---`/`+++` are still needed below to pick the language
+                let is_path_line = line.starts_with("--- ") || line.starts_with("+++ ");
+                if let Some(file) = patch.file_mut() {
+                    if file.meta.apply_extended_header(line) {
+                        in_binary_patch = line == "GIT binary patch";
+                        file.header[0] = banner_line(&file.meta);
+                        if !is_path_line {
+                            continue;
+                        }
+                    }
+                }
+            } else if let Some(meta) = FileMeta::from_binary_notice(line) {
+                // Names the file a `diff -r` line started, or starts one of its own
+                match patch.file_mut() {
+                    Some(file) if file.hunks.is_empty() && file.meta == FileMeta::new("", "") => file.meta = meta.clone(),
+                    _ => patch.start_file(meta.clone()),
+                }
+                patch.push(banner_line(&meta));
+                continue;
+            }
+            
+            if let Some(filename) = line.strip_prefix("--- ") {
+                // Only the old path is known so far, `+++` overrides it unless the file was deleted
+                let path = strip_path_prefix(filename, "a/");
+                current_language = self.path_language(path);
                 
-                result.push(DiffLine {
-                    line_type: DiffLineType::Added,
-                    content,
-                    syntax_highlights,
-                });
-            } else if line.starts_with('-') {
-                let content = line.chars().skip(1).collect::<String>();
-                let syntax_highlights = self.syntax_highlighter
-                    .highlight_line(&content, current_language)
-                    .unwrap_or_else(|_| vec![(SyntaxType::Normal, content.clone())]);
+                // The banner already names the file
+                if in_extended_header {
+                    continue;
+                }
                 
-                result.push(DiffLine {
-                    line_type: DiffLineType::Removed,
-                    content,
-                    syntax_highlights,
-                });
-            } else if line.starts_with(' ') {
-                let content = line.chars().skip(1).collect::<String>();
-                let syntax_highlights = self.syntax_highlighter
-                    .highlight_line(&content, current_language)
-                    .unwrap_or_else(|_| vec![(SyntaxType::Normal, content.clone())]);
+                // Loose lines such as `diff -u a b` may have started the file already
+                match patch.file_mut() {
+                    Some(file) if file.hunks.is_empty() && file.meta.old_path.is_empty() => file.meta.old_path = path.to_string(),
+                    _ => patch.start_file(FileMeta::new(path, "")),
+                }
+                patch.push(plain_line(DiffLineType::FileHeader, line));
+            } else if let Some(filename) = line.strip_prefix("+++ ") {
+                let path = strip_path_prefix(filename, "b/");
+                if path != "/dev/null" {
+                    current_language = self.path_language(path);
+                }
                 
-                result.push(DiffLine {
-                    line_type: DiffLineType::Context,
-                    content,
-                    syntax_highlights,
-                });
+                if in_extended_header {
+                    continue;
+                }
+                
+                if patch.file_mut().is_none_or(|file| !file.hunks.is_empty()) {
+                    patch.start_file(FileMeta::new("", path));
+                }
+                if let Some(file) = patch.file_mut() {
+                    file.meta.new_path = path.to_string();
+                    file.meta.apply_dev_null();
+                }
+                patch.push(plain_line(DiffLineType::FileHeader, line));
+            } else if line.starts_with("@@") {
+                in_extended_header = false;
+                let ranges = parse_hunk_header(line);
+                hunk_counts_known = ranges.is_some();
+                let (old_ranges, new_range) = ranges.unwrap_or((vec![HunkRange::default()], HunkRange::default()));
+                parent_lines = old_ranges.iter().copied().map(HunkCursor::starting_at).collect();
+                new_line = HunkCursor::starting_at(new_range);
+                patch.start_hunk(old_ranges, new_range, plain_line(DiffLineType::HunkHeader, line));
             } else {
                 let syntax_highlights = self.syntax_highlighter
                     .highlight_line(line, current_language)
                     .unwrap_or_else(|_| vec![(SyntaxType::Normal, line.to_string())]);
                 
-                result.push(DiffLine {
+                patch.push_loose(DiffLine {
                     line_type: DiffLineType::Context,
                     content: line.to_string(),
                     syntax_highlights,
+                    emphasis: Vec::new(),
+                    old_line_no: None,
+                    new_line_no: None,
+                    parents: Vec::new(),
+                    moved: None,
                 });
             }
         }
         
-        Ok(result)
+        self.flush_hunk(&mut patch, &mut hunk_old_side, &mut hunk_new_side, current_language);
+        
+        let mut patch = patch.finish();
+        for hunk in patch.hunks_mut() {
+            emphasize_changes(&mut hunk.lines);
+        }
+        
+        Ok(patch)
+    }
+    
+    fn path_language<'a>(&self, path: &'a str) -> Option<&'a str> {
+        if path == "/dev/null" {
+            return None;
+        }
+        self.syntax_highlighter.detect_language(Some(path))
+    }
+    
+    /// Highlights the hunk collected so far and starts a new one
+    fn flush_hunk(&self, patch: &mut PatchBuilder, old_side: &mut Vec<usize>, new_side: &mut Vec<usize>, language: Option<&str>) {
+        if let Some(hunk) = patch.hunk_mut() {
+            self.highlight_hunk_side(&mut hunk.lines, new_side, language);
+            self.highlight_hunk_side(&mut hunk.lines, old_side, language);
+        }
+        old_side.clear();
+        new_side.clear();
     }
+    
+    /// Highlights the lines at `indices` as one continuous fragment, so that a block comment or
+    /// multi-line string opened on one line of a hunk carries over to the following ones. The old
+    /// side is applied last so context lines end up coloured as in the old version.
+    fn highlight_hunk_side(&self, lines: &mut [DiffLine], indices: &[usize], language: Option<&str>) {
+        if indices.is_empty() {
+            return;
+        }
+        
+        let fragment = indices.iter()
+            .map(|&idx| lines[idx].content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        
+        if let Ok(highlights) = self.syntax_highlighter.highlight_document(&fragment, language) {
+            for (&idx, syntax_highlights) in indices.iter().zip(highlights) {
+                lines[idx].syntax_highlights = syntax_highlights;
+            }
+        }
+    }
+}
+
+/// A moved block needs this many alphanumeric characters, so that a lone `}` or blank line
+/// reappearing somewhere else does not count as a move (the same limit git uses)
+const MOVED_MIN_ALNUM_COUNT: usize = 20;
+
+/// Finds blocks of removed lines that reappear as added lines elsewhere in the diff, in the
+/// same or another hunk or file, and numbers them in `DiffLine::moved`. Each block pairs the
+/// longest run of consecutive added lines with an equal run of removed lines.
+pub fn mark_moved_lines(patch: &mut Patch) {
+    let mut lines = patch.lines_mut().collect::<Vec<_>>();
+    let mut removed_by_content: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (idx, line) in lines.iter().enumerate() {
+        if line.line_type == DiffLineType::Removed {
+            removed_by_content.entry(line.content.as_str()).or_default().push(idx);
+        }
+    }
+    
+    let run_length = |lines: &[&mut DiffLine], removed_start: usize, added_start: usize, used: &[bool]| {
+        (0..)
+            .take_while(|&offset| {
+                let (old, new) = (removed_start + offset, added_start + offset);
+                old < lines.len() && new < lines.len() && !used[old]
+                    && lines[old].line_type == DiffLineType::Removed
+                    && lines[new].line_type == DiffLineType::Added
+                    && lines[old].content == lines[new].content
+            })
+            .count()
+    };
+    
+    let mut blocks = Vec::new();
+    let mut used = vec![false; lines.len()];
+    let mut idx = 0;
+    while idx < lines.len() {
+        if lines[idx].line_type != DiffLineType::Added {
+            idx += 1;
+            continue;
+        }
+        
+        let best = removed_by_content.get(lines[idx].content.as_str())
+            .into_iter()
+            .flatten()
+            .map(|&removed_start| (removed_start, run_length(&lines, removed_start, idx, &used)))
+            .max_by_key(|&(removed_start, len)| (len, std::cmp::Reverse(removed_start)));
+        
+        match best {
+            Some((removed_start, len)) if len > 0 => {
+                let alnum_count = lines[idx..idx + len].iter()
+                    .map(|line| line.content.chars().filter(|ch| ch.is_alphanumeric()).count())
+                    .sum::<usize>();
+                if alnum_count >= MOVED_MIN_ALNUM_COUNT {
+                    used[removed_start..removed_start + len].fill(true);
+                    blocks.push((removed_start, idx, len));
+                }
+                idx += len;
+            },
+            _ => idx += 1,
+        }
+    }
+    
+    for (block, (removed_start, added_start, len)) in blocks.into_iter().enumerate() {
+        for idx in (removed_start..removed_start + len).chain(added_start..added_start + len) {
+            lines[idx].moved = Some(block);
+            // A moved line has no counterpart to compare words with
+            lines[idx].emphasis.clear();
+        }
+    }
+}
+
+/// The line shown instead of hunks when either side is binary. A missing side is named
+/// `/dev/null`, as in git.
+pub fn binary_summary_line(old_name: &str, new_name: &str, old_size: usize, new_size: usize) -> DiffLine {
+    let summary = format!("Binary files {} and {} differ ({} → {})", old_name, new_name, format_size(old_size), format_size(new_size));
+    plain_line(DiffLineType::BinarySummary, &summary)
+}
+
+/// The note shown above the hunks when the sides were decoded from different encodings, or
+/// in their place when the text is the same and only the encoding or byte order mark changed
+pub fn encoding_note_line(old_encoding: &str, new_encoding: &str, only_encoding: bool) -> DiffLine {
+    let label = if only_encoding { "Only the encoding differs" } else { "Encoding" };
+    plain_line(DiffLineType::EncodingNote, &format!("{}: {} → {}", label, old_encoding, new_encoding))
+}
+
+/// Formats a byte count with binary prefixes, e.g. `512 B` or `12.3 KiB`
+fn format_size(size: usize) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+    
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn split_lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+const NO_NEWLINE_MARKER: &str = "\\ No newline at end of file";
+
+/// The banner line summarizing a file's status and paths
+pub fn banner_line(meta: &FileMeta) -> DiffLine {
+    plain_line(DiffLineType::FileBanner(meta.clone()), &meta.summary())
+}
+
+/// A line that is shown as is, without syntax highlighting
+fn plain_line(line_type: DiffLineType, content: &str) -> DiffLine {
+    DiffLine {
+        line_type,
+        content: content.to_string(),
+        syntax_highlights: vec![(SyntaxType::Normal, content.to_string())],
+        emphasis: Vec::new(),
+        old_line_no: None,
+        new_line_no: None,
+        parents: Vec::new(),
+        moved: None,
+    }
+}
+
+/// Where `parse_diff` is inside a commit section
+#[derive(Clone, Copy)]
+enum CommitPart {
+    /// `Author:`, `Date:`, ... up to the first blank line
+    Header,
+    Message,
+}
+
+/// Matches `commit <sha>`, optionally followed by decorations like `(HEAD -> main)`
+fn is_commit_start(line: &str) -> bool {
+    line.strip_prefix("commit ")
+        .and_then(|rest| rest.split_whitespace().next())
+        .is_some_and(|sha| sha.len() >= 7 && sha.chars().all(|ch| ch.is_ascii_hexdigit()))
+}
+
+/// How much unchanged text surrounds each change of a generated diff
+struct HunkContext {
+    lines: usize,
+    /// Line ranges of the functions on each side, for `--function-context`
+    old_functions: Vec<Range<usize>>,
+    new_functions: Vec<Range<usize>>,
+}
+
+impl HunkContext {
+    /// Groups the ops of a diff into hunks like `TextDiff::grouped_ops`, but keeps as much
+    /// context before and after each change as its enclosing function needs
+    fn group_ops(&self, ops: &[DiffOp]) -> Vec<Vec<DiffOp>> {
+        let mut groups = Vec::new();
+        let mut group = Vec::new();
+        
+        for (idx, op) in ops.iter().enumerate() {
+            let DiffOp::Equal { old_index, new_index, len } = *op else {
+                group.push(*op);
+                continue;
+            };
+            
+            let after = if group.is_empty() { 0 } else { self.lines_after(old_index, new_index).min(len) };
+            let before = if idx + 1 == ops.len() { 0 } else { self.lines_before(old_index + len, new_index + len).min(len) };
+            
+            // Changes close enough for their context to touch share a hunk
+            if !group.is_empty() && after + before >= len {
+                group.push(*op);
+                continue;
+            }
+            if after > 0 {
+                group.push(DiffOp::Equal { old_index, new_index, len: after });
+            }
+            if !group.is_empty() {
+                groups.push(std::mem::take(&mut group));
+            }
+            if before > 0 {
+                group.push(DiffOp::Equal { old_index: old_index + len - before, new_index: new_index + len - before, len: before });
+            }
+        }
+        
+        if group.iter().any(|op| op.tag() != DiffTag::Equal) {
+            groups.push(group);
+        }
+        groups
+    }
+    
+    /// Context wanted after a change ending just before `old_line`/`new_line`
+    fn lines_after(&self, old_line: usize, new_line: usize) -> usize {
+        let function_end = |functions: &[Range<usize>], line: usize| {
+            line.checked_sub(1)
+                .and_then(|last| innermost_function(functions, last))
+                .map_or(0, |function| function.end.saturating_sub(line))
+        };
+        self.lines
+            .max(function_end(&self.old_functions, old_line))
+            .max(function_end(&self.new_functions, new_line))
+    }
+    
+    /// Context wanted before a change starting at `old_line`/`new_line`
+    fn lines_before(&self, old_line: usize, new_line: usize) -> usize {
+        let function_start = |functions: &[Range<usize>], line: usize| {
+            innermost_function(functions, line).map_or(0, |function| line - function.start)
+        };
+        self.lines
+            .max(function_start(&self.old_functions, old_line))
+            .max(function_start(&self.new_functions, new_line))
+    }
+}
+
+fn innermost_function(functions: &[Range<usize>], line: usize) -> Option<&Range<usize>> {
+    functions.iter()
+        .filter(|function| function.contains(&line))
+        .min_by_key(|function| function.len())
+}
+
+/// Tracks the position inside one side of a hunk while its lines are being parsed
+#[derive(Default)]
+struct HunkCursor {
+    next: usize,
+    remaining: usize,
+}
+
+impl HunkCursor {
+    fn starting_at(range: HunkRange) -> Self {
+        Self { next: range.start, remaining: range.count }
+    }
+    
+    /// Returns the line number of the current line on this side and moves past it, or `None`
+    /// once the hunk has no lines left on this side
+    fn advance(&mut self) -> Option<usize> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        self.next += 1;
+        Some(self.next - 1)
+    }
+}
+
+/// Parses `@@ -12,5 +13,6 @@` into the `(start, count)` of the old and new side.
+/// A missing count means one line, as in `@@ -3 +3 @@`. The `@@@ -1,5 -1,4 +1,7 @@@` header
+/// of a combined diff has one old range per merge parent, one less than the number of `@`s.
+fn parse_hunk_header(line: &str) -> Option<(Vec<HunkRange>, HunkRange)> {
+    let marker_len = line.chars().take_while(|&ch| ch == '@').count();
+    if marker_len < 2 {
+        return None;
+    }
+    
+    let mut parts = line[marker_len..].split_whitespace();
+    let old_ranges = (1..marker_len)
+        .map(|_| parse_hunk_range(parts.next()?.strip_prefix('-')?))
+        .collect::<Option<Vec<_>>>()?;
+    let new_range = parse_hunk_range(parts.next()?.strip_prefix('+')?)?;
+    Some((old_ranges, new_range))
+}
+
+/// Reads the per-parent markers at the start of a combined diff line such as `+ ` or ` -`.
+/// A line marked `-` for any parent was removed by the merge, one marked `+` was added.
+fn parse_combined_markers(line: &str, parents: usize) -> Option<(DiffLineType, Vec<ParentChange>)> {
+    let mut markers = line.chars()
+        .take(parents)
+        .map(ParentChange::from_marker)
+        .collect::<Option<Vec<_>>>()?;
+    // Editors may strip the trailing spaces of an all-context line
+    markers.resize(parents, ParentChange::Unchanged);
+    
+    let line_type = if markers.contains(&ParentChange::Removed) {
+        DiffLineType::Removed
+    } else if markers.contains(&ParentChange::Added) {
+        DiffLineType::Added
+    } else {
+        DiffLineType::Context
+    };
+    Some((line_type, markers))
+}
+
+/// The hunk header range of 0-based lines. As in `diff -u`, an empty side names the line
+/// before the gap, so a file created from nothing is `-0,0`.
+fn hunk_range(range: Range<usize>) -> HunkRange {
+    match range.len() {
+        0 => HunkRange { start: range.start, count: 0 },
+        count => HunkRange { start: range.start + 1, count },
+    }
+}
+
+fn parse_hunk_range(range: &str) -> Option<HunkRange> {
+    match range.split_once(',') {
+        Some((start, count)) => Some(HunkRange { start: start.parse().ok()?, count: count.parse().ok()? }),
+        None => Some(HunkRange { start: range.parse().ok()?, count: 1 }),
+    }
+}
+
+/// Below this similarity a removed/added pair is treated as a rewrite and left without emphasis
+const EMPHASIS_MIN_RATIO: f32 = 0.4;
+
+/// Pairs each run of removed lines with the run of added lines directly following it and marks
+/// the words that differ between the two lines of every pair.
+fn emphasize_changes(lines: &mut [DiffLine]) {
+    let mut idx = 0;
+    while idx < lines.len() {
+        let run_start = idx;
+        let removed = line_run(lines, &mut idx, DiffLineType::Removed);
+        let added = line_run(lines, &mut idx, DiffLineType::Added);
+        if idx == run_start {
+            idx += 1;
+            continue;
+        }
+        
+        let changes = |run: Vec<usize>| run.into_iter().filter(|&idx| lines[idx].line_type != DiffLineType::NoNewline).collect::<Vec<_>>();
+        for (old_idx, new_idx) in changes(removed).into_iter().zip(changes(added)) {
+            let (old_emphasis, new_emphasis) = word_changes(&lines[old_idx].content, &lines[new_idx].content);
+            lines[old_idx].emphasis = old_emphasis;
+            lines[new_idx].emphasis = new_emphasis;
+        }
+    }
+}
+
+/// Indices of the run of `line_type` lines starting at `idx`, which is moved past the run. A
+/// `\ No newline at end of file` marker belongs to the line before it, so it stays in the run.
+pub(crate) fn line_run<T: Borrow<DiffLine>>(lines: &[T], idx: &mut usize, line_type: DiffLineType) -> Vec<usize> {
+    let mut run = Vec::new();
+    while let Some(line) = lines.get(*idx).map(Borrow::borrow) {
+        let marker = line.line_type == DiffLineType::NoNewline && !run.is_empty();
+        if line.line_type != line_type && !marker {
+            break;
+        }
+        run.push(*idx);
+        *idx += 1;
+    }
+    run
 }
 
+fn word_changes(old: &str, new: &str) -> (Vec<Range<usize>>, Vec<Range<usize>>) {
+    let old_tokens = tokenize_words(old);
+    let new_tokens = tokenize_words(new);
+    let diff = TextDiff::from_slices(&old_tokens, &new_tokens);
+    if diff.ratio() < EMPHASIS_MIN_RATIO {
+        return (Vec::new(), Vec::new());
+    }
+    
+    let mut old_ranges: Vec<Range<usize>> = Vec::new();
+    let mut new_ranges: Vec<Range<usize>> = Vec::new();
+    let (mut old_pos, mut new_pos) = (0, 0);
+    
+    for change in diff.iter_all_changes() {
+        let len = change.value().len();
+        let (ranges, pos) = match change.tag() {
+            ChangeTag::Equal => {
+                old_pos += len;
+                new_pos += len;
+                continue;
+            },
+            ChangeTag::Delete => (&mut old_ranges, &mut old_pos),
+            ChangeTag::Insert => (&mut new_ranges, &mut new_pos),
+        };
+        
+        match ranges.last_mut() {
+            Some(last) if last.end == *pos => last.end += len,
+            _ => ranges.push(*pos..*pos + len),
+        }
+        *pos += len;
+    }
+    
+    (old_ranges, new_ranges)
+}
+
+/// Splits a line into identifier-like words, whitespace runs and single punctuation characters,
+/// which gives finer emphasis than whitespace-separated words for code.
+fn tokenize_words(line: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut chars = line.char_indices().peekable();
+    
+    while let Some((idx, ch)) = chars.next() {
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        let continues = match chars.peek() {
+            Some(&(_, next)) => (is_word(ch) && is_word(next)) || (ch.is_whitespace() && next.is_whitespace()),
+            None => false,
+        };
+        if !continues {
+            let end = idx + ch.len_utf8();
+            tokens.push(&line[start..end]);
+            start = end;
+        }
+    }
+    
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `git diff` touching a Python file, then Rust files (one deleted), then a text file
+    const MULTI_FILE_GIT_DIFF: &str = "\
+diff --git a/a.py b/a.py
+index b859599..ea74361 100644
+--- a/a.py
++++ b/a.py
+@@ -1,2 +1,2 @@
+ def f():
+-    return 1
++    return 2
+diff --git a/b.rs b/b.rs
+index f328e4d..4dbe88e 100644
+--- a/b.rs
++++ b/b.rs
+@@ -1 +1 @@
+-fn main() {}
++fn main() { let x = 1; }
+diff --git a/c.rs b/c.rs
+deleted file mode 100644
+index 3dd43dd..0000000
+--- a/c.rs
++++ /dev/null
+@@ -1 +0,0 @@
+-fn gone() {}
+diff --git a/notes.txt b/notes.txt
+new file mode 100644
+index 0000000..223f3cc
+--- /dev/null
++++ b/notes.txt
+@@ -0,0 +1 @@
++def g(): pass
+";
+
+    fn keywords(line: &DiffLine) -> Vec<&str> {
+        line.syntax_highlights.iter()
+            .filter(|(syntax_type, _)| *syntax_type == SyntaxType::Keyword)
+            .map(|(_, text)| text.trim())
+            .collect()
+    }
+
+    fn find<'a>(patch: &'a Patch, line_type: DiffLineType, content: &str) -> &'a DiffLine {
+        patch.lines()
+            .find(|line| line.line_type == line_type && line.content == content)
+            .unwrap_or_else(|| panic!("no {:?} line {:?}", line_type, content))
+    }
+
+    fn only(line: &DiffLine, syntax_type: SyntaxType) {
+        assert!(line.syntax_highlights.iter().all(|(found, _)| *found == syntax_type), "{:?}", line);
+    }
+
+    #[test]
+    fn language_follows_each_file_of_a_git_diff() {
+        let patch = DiffProcessor::new().parse_diff(MULTI_FILE_GIT_DIFF).unwrap();
+
+        assert_eq!(keywords(find(&patch, DiffLineType::Context, "def f():")), ["def"]);
+        assert_eq!(keywords(find(&patch, DiffLineType::Added, "    return 2")), ["return"]);
+        // Previously still highlighted as Python, where `fn` and `let` are not keywords
+        assert_eq!(keywords(find(&patch, DiffLineType::Removed, "fn main() {}")), ["fn"]);
+        assert_eq!(keywords(find(&patch, DiffLineType::Added, "fn main() { let x = 1; }")), ["fn", "let"]);
+    }
+
+    #[test]
+    fn deleted_file_takes_language_from_old_path() {
+        let patch = DiffProcessor::new().parse_diff(MULTI_FILE_GIT_DIFF).unwrap();
+
+        assert_eq!(keywords(find(&patch, DiffLineType::Removed, "fn gone() {}")), ["fn"]);
+    }
+
+    #[test]
+    fn unknown_extension_resets_language() {
+        let patch = DiffProcessor::new().parse_diff(MULTI_FILE_GIT_DIFF).unwrap();
+
+        let added = find(&patch, DiffLineType::Added, "def g(): pass");
+        assert_eq!(added.syntax_highlights, vec![(SyntaxType::Normal, "def g(): pass".to_string())]);
+    }
+
+    #[test]
+    fn plain_unified_diff_paths_ignore_timestamps() {
+        let diff = "\
+--- o.py\t2026-10-17 17:53:08.847573800 +0000
++++ n.py\t2026-10-17 17:53:08.847573800 +0000
+@@ -1 +1 @@
+-def a(): pass
++def b(): pass
+";
+        let patch = DiffProcessor::new().parse_diff(diff).unwrap();
+
+        assert_eq!(keywords(find(&patch, DiffLineType::Removed, "def a(): pass")), ["def", "pass"]);
+        assert_eq!(keywords(find(&patch, DiffLineType::Added, "def b(): pass")), ["def", "pass"]);
+    }
+
+    #[test]
+    fn parsed_diff_is_split_into_files_and_hunks() {
+        let patch = DiffProcessor::new().parse_diff(MULTI_FILE_GIT_DIFF).unwrap();
+        let files = patch.files().collect::<Vec<_>>();
+        let summary = files.iter()
+            .map(|file| (file.meta.new_path.as_str(), file.meta.status.clone(), file.added(), file.removed()))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, [
+            ("a.py", FileStatus::Modified, 1, 1),
+            ("b.rs", FileStatus::Modified, 1, 1),
+            ("c.rs", FileStatus::Deleted, 0, 1),
+            ("notes.txt", FileStatus::Added, 1, 0),
+        ]);
+        assert_eq!(files[0].hunks[0].old_ranges, [HunkRange { start: 1, count: 2 }]);
+        assert_eq!(files[2].hunks[0].new_range, HunkRange { start: 0, count: 0 });
+        assert_eq!(files[0].header.len(), 1);
+        
+        let log = "\
+commit 1111111
+Author: A <a@example.com>
+
+    Second
+
+--- x.txt\t2026-10-17 17:53:08 +0000
++++ y.txt\t2026-10-17 17:53:08 +0000
+@@ -1 +1 @@
+-a
++b
+commit 2222222
+Author: A <a@example.com>
+
+    First
+";
+        let patch = DiffProcessor::new().parse_diff(log).unwrap();
+        let shas = patch.commits.iter().map(|commit| commit.sha.as_deref()).collect::<Vec<_>>();
+        assert_eq!(shas, [Some("1111111"), Some("2222222")]);
+        let file = &patch.commits[0].files[0];
+        assert_eq!((file.meta.old_path.as_str(), file.meta.new_path.as_str()), ("x.txt", "y.txt"));
+        assert_eq!(file.hunks[0].lines.len(), 2);
+        assert!(patch.commits[1].files.is_empty());
+    }
+    
+    #[test]
+    fn hunks_of_git_log_output_hold_exactly_their_counted_lines() {
+        // `git log -p` of two commits; a blank line ends each commit after its last hunk
+        let log = "\
+commit 6e0c5cc12a88cdf3ba72035e777a818544d3dabe
+Author: A <a@example.com>
+Date:   Sat Oct 17 11:00:00 2026 +0000
+
+    Change x, add y
+
+diff --git a/x.rs b/x.rs
+index 52d34dd..57d459a 100644
+--- a/x.rs
++++ b/x.rs
+@@ -1,2 +1,3 @@
+ fn one() {}
+-fn two() {}
++fn three() {}
++
+diff --git a/y.txt b/y.txt
+new file mode 100644
+index 0000000..ce01362
+--- /dev/null
++++ b/y.txt
+@@ -0,0 +1 @@
++hello
+
+commit a1f48beaf08a06059f19479936b2b41144499de5
+Author: A <a@example.com>
+Date:   Sat Oct 17 10:00:00 2026 +0000
+
+    Add x
+
+diff --git a/x.rs b/x.rs
+new file mode 100644
+index 0000000..52d34dd
+--- /dev/null
++++ b/x.rs
+@@ -0,0 +1,2 @@
++fn one() {}
++fn two() {}
+";
+        let patch = DiffProcessor::new().parse_diff(log).unwrap();
+        assert_eq!(patch.commits.len(), 2);
+        for hunk in patch.files().flat_map(|file| &file.hunks) {
+            let count = |line_type: DiffLineType| hunk.lines.iter().filter(|line| line.line_type == line_type).count();
+            let context = count(DiffLineType::Context);
+            assert_eq!(context + count(DiffLineType::Removed), hunk.old_ranges[0].count, "{}", hunk.header.content);
+            assert_eq!(context + count(DiffLineType::Added), hunk.new_range.count, "{}", hunk.header.content);
+            assert_eq!(hunk.lines.len(), hunk.old_ranges[0].count + hunk.new_range.count - context);
+        }
+        // The blank line before the second commit follows the last hunk of the first one
+        let y = &patch.commits[0].files[1];
+        assert_eq!((y.meta.new_path.as_str(), y.hunks[0].trailer.len()), ("y.txt", 1));
+    }
+    
+    #[test]
+    fn stray_lines_inside_hunks_do_not_break_highlighting() {
+        // A loose line while the hunk still expects lines, then a line the counts take
+        let patch = DiffProcessor::new().parse_diff("@@ -1 +1 @@\ncopy from q\n+++ /dev/null\n").unwrap();
+        let hunk = &patch.files().next().unwrap().hunks[0];
+        assert_eq!((hunk.lines.len(), hunk.trailer.len()), (0, 2));
+        
+        // A bare `@@` has no counts, so any line may follow
+        let diff = "--- a/x.rs\n+++ b/x.rs\n@@\n-fn a() {}\nstray\n+fn b() {}\n";
+        let patch = DiffProcessor::new().parse_diff(diff).unwrap();
+        let hunk = &patch.files().next().unwrap().hunks[0];
+        assert_eq!(hunk.lines.iter().map(|line| line.content.as_str()).collect::<Vec<_>>(), ["fn a() {}"]);
+        assert_eq!(keywords(&hunk.lines[0]), ["fn"]);
+        assert_eq!(hunk.trailer.len(), 2);
+    }
+    
+    #[test]
+    fn loose_lines_between_hunks_stay_in_their_file() {
+        let diff = "\
+diff --git a/x.rs b/x.rs
+--- a/x.rs
++++ b/x.rs
+@@ -1 +1 @@
+-fn a() {}
++fn b() {}
+Some note
+@@ -5 +5 @@
+-fn c() {}
++fn d() {}
+";
+        let patch = DiffProcessor::new().parse_diff(diff).unwrap();
+        let files = patch.files().collect::<Vec<_>>();
+        assert_eq!(files.len(), 1);
+        assert_eq!((files[0].hunks.len(), files[0].added(), files[0].removed()), (2, 2, 2));
+        assert_eq!(files[0].hunks[0].trailer[0].content, "Some note");
+        assert_eq!(keywords(find(&patch, DiffLineType::Added, "fn d() {}")), ["fn"]);
+    }
+    
+    #[test]
+    fn commit_sections_keep_their_stat_block() {
+        let log = "\
+commit 6e0c5cc12a88cdf3ba72035e777a818544d3dabe
+Author: A <a@example.com>
+Date:   Sat Oct 17 11:00:00 2026 +0000
+
+    Change x, add y
+---
+ x.rs  | 3 ++-
+ y.txt | 1 +
+ 2 files changed, 3 insertions(+), 1 deletion(-)
+
+diff --git a/x.rs b/x.rs
+index 52d34dd..57d459a 100644
+--- a/x.rs
++++ b/x.rs
+@@ -1,2 +1,3 @@
+ fn one() {}
+-fn two() {}
++fn three() {}
++
+diff --git a/y.txt b/y.txt
+new file mode 100644
+index 0000000..ce01362
+--- /dev/null
++++ b/y.txt
+@@ -0,0 +1 @@
++hello
+";
+        let patch = DiffProcessor::new().parse_diff(log).unwrap();
+        let commit = &patch.commits[0];
+        let types = commit.header.iter().map(|line| line.line_type.clone()).collect::<Vec<_>>();
+        assert_eq!(types[..3], [DiffLineType::CommitHeader, DiffLineType::CommitMeta, DiffLineType::CommitMeta]);
+        assert!(types[3..].iter().all(|line_type| *line_type == DiffLineType::CommitMessage));
+        assert_eq!(commit.header.iter().map(|line| line.content.as_str()).find(|content| content.starts_with('-')), Some("---"));
+        assert_eq!(commit.header.len(), 10);
+        let counts = commit.files.iter().map(|file| (file.added(), file.removed())).collect::<Vec<_>>();
+        assert_eq!(counts, [(2, 1), (1, 0)]);
+    }
+    
+    #[test]
+    fn git_header_paths_lose_prefixes() {
+        assert_eq!(split_git_paths("a/src/b/x.rs b/src/b/x.rs"), ("src/b/x.rs", "src/b/x.rs"));
+        assert_eq!(split_git_paths("a/old.rs b/new.rs"), ("old.rs", "new.rs"));
+        assert_eq!(split_git_paths("a/my b/x.rs b/my b/x.rs"), ("my b/x.rs", "my b/x.rs"));
+    }
+
+    #[test]
+    fn path_lines_correct_the_git_header_paths() {
+        // Copied paths differ, so the `diff --git` line alone splits at the wrong ` b/`
+        let diff = "\
+diff --git a/my b/x.rs b/my b/y.rs
+similarity index 100%
+copy from my b/x.rs
+copy to my b/y.rs
+diff --git a/my b/x.rs b/my b/z.rs
+--- a/my b/x.rs\t
++++ b/my b/z.rs\t
+@@ -1 +1 @@
+-fn a() {}
++fn b() {}
+";
+        let patch = DiffProcessor::new().parse_diff(diff).unwrap();
+        let paths = patch.files()
+            .map(|file| (file.meta.old_path.as_str(), file.meta.new_path.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(paths, [("my b/x.rs", "my b/y.rs"), ("my b/x.rs", "my b/z.rs")]);
+        assert_eq!(patch.files().nth(1).unwrap().header[0].content, "modified: my b/z.rs");
+    }
+
+    #[test]
+    fn diff_r_separators_start_files_without_highlighting() {
+        let diff = "\
+diff -ruN old/a.py new/a.py
+--- old/a.py\t2026-10-17 17:53:08 +0000
++++ new/a.py\t2026-10-17 17:53:08 +0000
+@@ -1 +1 @@
+-def a(): pass
++def b(): pass
+diff -ruN old/if.py new/if.py
+--- old/if.py\t2026-10-17 17:53:08 +0000
++++ new/if.py\t2026-10-17 17:53:08 +0000
+@@ -1 +1 @@
+-x = 1
++x = 2
+";
+        let patch = DiffProcessor::new().parse_diff(diff).unwrap();
+        let paths = patch.files()
+            .map(|file| (file.meta.old_path.as_str(), file.meta.new_path.as_str(), file.header.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(paths, [("old/a.py", "new/a.py", 3), ("old/if.py", "new/if.py", 3)]);
+        
+        // Previously a loose line highlighted as Python, with `if` as a keyword
+        let separator = find(&patch, DiffLineType::FileHeader, "diff -ruN old/if.py new/if.py");
+        assert_eq!(separator.syntax_highlights, vec![(SyntaxType::Normal, separator.content.clone())]);
+    }
+
+    #[test]
+    fn combined_diff_tracks_every_parent() {
+        let diff = "\
+diff --cc x.rs
+index b6e0ee2,45d4a73..f3984e2
+--- a/x.rs
++++ b/x.rs
+@@@ -1,4 -1,4 +1,4 @@@
+  fn a() {
+-     3
+ -    2
+++    4
+  }
+diff --git a/y.rs b/y.rs
+--- a/y.rs
++++ b/y.rs
+@@ -1 +1 @@
+-fn y() {}
++fn z() {}
+";
+        let patch = DiffProcessor::new().parse_diff(diff).unwrap();
+
+        let first_parent = find(&patch, DiffLineType::Removed, "    3");
+        assert_eq!(first_parent.parents, [ParentChange::Removed, ParentChange::Unchanged]);
+        assert_eq!(first_parent.old_line_no, Some(2));
+        let second_parent = find(&patch, DiffLineType::Removed, "    2");
+        assert_eq!(second_parent.parents, [ParentChange::Unchanged, ParentChange::Removed]);
+        assert_eq!(second_parent.old_line_no, None);
+        let merged = find(&patch, DiffLineType::Added, "    4");
+        assert_eq!((merged.old_line_no, merged.new_line_no), (None, Some(2)));
+        assert_eq!(keywords(find(&patch, DiffLineType::Context, "fn a() {")), ["fn"]);
+        assert_eq!(find(&patch, DiffLineType::Context, "}").old_line_no, Some(3));
+
+        // The following ordinary diff goes back to a single marker column
+        assert!(find(&patch, DiffLineType::Added, "fn z() {}").parents.is_empty());
+    }
+
+    #[test]
+    fn missing_final_newline_gets_a_marker() {
+        let processor = DiffProcessor::new();
+        let patch = processor.generate_diff("a\nb", "a\nb\n", &DiffOptions::new().old_name("x.txt").new_name("x.txt")).unwrap();
+        let types = patch.lines().skip(3).map(|line| line.line_type.clone()).collect::<Vec<_>>();
+        assert_eq!(types, [DiffLineType::Context, DiffLineType::Removed, DiffLineType::NoNewline, DiffLineType::Added]);
+        
+        // The marker between them doesn't keep the pair from being emphasized
+        let patch = processor.generate_diff("a\nlet x = 1;", "a\nlet x = 2;\n", &DiffOptions::new()).unwrap();
+        let emphasized = |line: &DiffLine| line.emphasis.iter().map(|range| line.content[range.clone()].to_string()).collect::<Vec<_>>();
+        assert_eq!(emphasized(find(&patch, DiffLineType::Removed, "let x = 1;")), ["1"]);
+        assert_eq!(emphasized(find(&patch, DiffLineType::Added, "let x = 2;")), ["2"]);
+
+        let diff = "\
+--- a/x.rs
++++ b/x.rs
+@@ -1 +1 @@
+-fn a() {}
+\\ No newline at end of file
++fn a() {}
+";
+        let patch = processor.parse_diff(diff).unwrap();
+        assert_eq!(find(&patch, DiffLineType::NoNewline, "\\ No newline at end of file").syntax_highlights.len(), 1);
+        assert_eq!(find(&patch, DiffLineType::Added, "fn a() {}").new_line_no, Some(1));
+    }
+
+    #[test]
+    fn function_context_widens_hunks_to_the_enclosing_function() {
+        let old = "fn one() {\n    let a = 1;\n    let b = 2;\n    let c = 3;\n    a + b + c\n}\n\nfn two() {}\n";
+        let new = old.replace("let b = 2", "let b = 20");
+        let hunk_headers = |processor: DiffProcessor| {
+            processor.generate_diff(old, &new, &DiffOptions::new().old_name("x.rs").new_name("x.rs")).unwrap()
+                .files()
+                .flat_map(|file| &file.hunks)
+                .map(|hunk| hunk.header.content.clone())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(hunk_headers(DiffProcessor::new().context_lines(0)), ["@@ -3,1 +3,1 @@"]);
+        assert_eq!(hunk_headers(DiffProcessor::new().context_lines(1)), ["@@ -2,3 +2,3 @@"]);
+        assert_eq!(hunk_headers(DiffProcessor::new().context_lines(0).function_context(true)), ["@@ -1,6 +1,6 @@"]);
+    }
+
+    #[test]
+    fn whitespace_options_compare_normalized_lines() {
+        let old = "fn a() {\n    let x = 1;\n    x\n}\n";
+        let new = "fn a() {\r\n        let  x = 1;   \r\n\r\n    x\r\n}\r\n";
+        let with = |whitespace: WhitespaceOptions| DiffProcessor::new().whitespace(whitespace);
+
+        assert!(with(WhitespaceOptions::default()).differs(old, new));
+        assert!(with(WhitespaceOptions { ignore_amount: true, ..Default::default() }).differs(old, new));
+        assert!(!with(WhitespaceOptions { ignore_amount: true, ignore_blank_lines: true, ..Default::default() }).differs(old, new));
+        assert!(!with(WhitespaceOptions { ignore_all: true, ignore_blank_lines: true, ..Default::default() }).differs(old, new));
+        assert!(with(WhitespaceOptions { ignore_eol: true, ..Default::default() }).differs(old, new));
+        assert!(!with(WhitespaceOptions { ignore_eol: true, ..Default::default() }).differs(old, &old.replace('\n', "\r\n")));
+
+        // Lines equal under -b are context, shown as written in the old version
+        let patch = with(WhitespaceOptions { ignore_amount: true, ..Default::default() })
+            .generate_diff(old, new, &DiffOptions::new())
+            .unwrap();
+        assert_eq!(find(&patch, DiffLineType::Context, "    let x = 1;").new_line_no, Some(2));
+        assert_eq!(find(&patch, DiffLineType::Added, "\r").new_line_no, Some(3));
+
+        // `a b` and `ab` still differ under -b
+        assert!(with(WhitespaceOptions { ignore_amount: true, ..Default::default() }).differs("a b\n", "ab\n"));
+    }
+
+    #[test]
+    fn moved_blocks_are_found_across_files() {
+        let diff = "\
+diff --git a/a.rs b/a.rs
+--- a/a.rs
++++ b/a.rs
+@@ -1,4 +1,1 @@
+-fn helper() {
+-    compute_something_long();
+-}
+ fn main() {}
+diff --git a/b.rs b/b.rs
+--- a/b.rs
++++ b/b.rs
+@@ -1 +1,5 @@
+ fn other() {}
++}
++fn helper() {
++    compute_something_long();
++}
+";
+        let mut patch = DiffProcessor::new().parse_diff(diff).unwrap();
+        mark_moved_lines(&mut patch);
+
+        let moved = |line_type: DiffLineType| patch.lines()
+            .filter(|line| line.line_type == line_type)
+            .map(|line| line.moved)
+            .collect::<Vec<_>>();
+        assert_eq!(moved(DiffLineType::Removed), [Some(0), Some(0), Some(0)]);
+        // The lone `}` before the block is too short to count as moved on its own
+        assert_eq!(moved(DiffLineType::Added), [None, Some(0), Some(0), Some(0)]);
+    }
+
+    #[test]
+    fn binary_summary_shows_both_sizes() {
+        let line = binary_summary_line("a.png", "b.png", 12_595, 14_438);
+        assert_eq!(line.content, "Binary files a.png and b.png differ (12.3 KiB → 14.1 KiB)");
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(3 * 1024 * 1024), "3.0 MiB");
+    }
+
+    #[test]
+    fn generated_hunks_are_highlighted_from_the_whole_file() {
+        // The comment and the docstring open further up than the hunk's context reaches
+        let notes = "   note\n".repeat(5);
+        let rust = |name: &str| format!("/* Opens a comment\n{}   fn {}() {{}}\n*/\n", notes, name);
+        let python = |value: u8| format!("def f():\n    \"\"\"Docstring\n{}    return {}\n    \"\"\"\n", notes, value);
+        let processor = DiffProcessor::new();
+
+        let patch = processor.generate_diff(&rust("old"), &rust("new"), &DiffOptions::new().old_name("a/lib.rs").new_name("b/lib.rs")).unwrap();
+        only(find(&patch, DiffLineType::Removed, "   fn old() {}"), SyntaxType::Comment);
+        only(find(&patch, DiffLineType::Added, "   fn new() {}"), SyntaxType::Comment);
+
+        let patch = processor.generate_diff(&python(1), &python(2), &DiffOptions::new().old_name("a/f.py").new_name("b/f.py")).unwrap();
+        only(find(&patch, DiffLineType::Removed, "    return 1"), SyntaxType::String);
+        only(find(&patch, DiffLineType::Added, "    return 2"), SyntaxType::String);
+    }
+
+    #[test]
+    fn block_comments_and_docstrings_carry_over_hunk_lines() {
+        let diff = "\
+--- a/lib.rs
++++ b/lib.rs
+@@ -1,3 +1,3 @@
+ /* Opens a comment
+-   fn old() {}
++   fn new() {}
+ */
+--- a/a.py
++++ b/a.py
+@@ -1,4 +1,4 @@
+ def f():
+     \"\"\"Docstring
+-    return 1
++    return 2
+     \"\"\"
+";
+        let patch = DiffProcessor::new().parse_diff(diff).unwrap();
+
+        // Each line on its own would read as code, with `fn` and `return` as keywords
+        only(find(&patch, DiffLineType::Removed, "   fn old() {}"), SyntaxType::Comment);
+        only(find(&patch, DiffLineType::Added, "   fn new() {}"), SyntaxType::Comment);
+        only(find(&patch, DiffLineType::Removed, "    return 1"), SyntaxType::String);
+        only(find(&patch, DiffLineType::Added, "    return 2"), SyntaxType::String);
+    }
+
+    #[test]
+    fn word_changes_emphasize_changed_tokens_of_similar_lines() {
+        // Changed tokens separated by unchanged whitespace get a range each
+        assert_eq!(word_changes("let count = total + 1;", "let count = total - 2;"), (vec![18..19, 20..21], vec![18..19, 20..21]));
+        // Adjacent changed tokens merge into one range, measured in bytes
+        let emphasized = |old: &'static str, new: &'static str| {
+            let (old_ranges, new_ranges) = word_changes(old, new);
+            let slices = |line: &'static str, ranges: Vec<Range<usize>>| ranges.into_iter().map(|range| &line[range]).collect::<Vec<_>>();
+            (slices(old, old_ranges), slices(new, new_ranges))
+        };
+        assert_eq!(emphasized("call(a, b)", "call(new_arg, b)"), (vec!["a"], vec!["new_arg"]));
+        assert_eq!(emphasized("x = \"é\";", "x = \"ü!\";"), (vec!["é"], vec!["ü!"]));
+
+        // 2 of 5 tokens on each side in common is exactly `EMPHASIS_MIN_RATIO`
+        assert_eq!(word_changes("a.b.c", "x.y.z"), (vec![0..1, 2..3, 4..5], vec![0..1, 2..3, 4..5]));
+        // Any less and the line counts as rewritten, without emphasis
+        assert_eq!(word_changes("a.b.c", "x.y-z"), (vec![], vec![]));
+        assert_eq!(word_changes("return value;", "break;"), (vec![], vec![]));
+    }
+}
@@ -0,0 +1,208 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use anyhow::{Context, Result};
+use encoding_rs::{UTF_16BE, UTF_16LE, UTF_8};
+
+/// Bytes git looks at for a NUL when deciding whether a file is binary
+const BINARY_SNIFF_LEN: usize = 8000;
+const HEXDUMP_WIDTH: usize = 16;
+
+/// How files are turned into text before diffing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadOptions {
+    /// Show binary files as a hex dump instead of summarizing them
+    pub hexdump: bool,
+    /// Encoding of files without a byte order mark; `None` sniffs for UTF-8
+    pub encoding: Option<Encoding>,
+}
+
+/// A text encoding from the WHATWG Encoding Standard
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Encoding(&'static encoding_rs::Encoding);
+
+impl Encoding {
+    /// Looks up an encoding by any of its labels, e.g. `shift_jis`, `latin1` or `utf-16le`
+    pub fn for_label(label: &str) -> Option<Self> {
+        encoding_rs::Encoding::for_label(label.as_bytes()).map(Encoding)
+    }
+
+    /// Canonical name of the encoding, e.g. `Shift_JIS`
+    pub fn name(&self) -> &'static str {
+        self.0.name()
+    }
+}
+
+/// Encoding a text file was decoded from, and whether it started with a byte order mark
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextEncoding {
+    pub encoding: &'static encoding_rs::Encoding,
+    pub bom: bool,
+}
+
+impl Default for TextEncoding {
+    fn default() -> Self {
+        TextEncoding { encoding: UTF_8, bom: false }
+    }
+}
+
+impl fmt::Display for TextEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.encoding.name())?;
+        if self.bom {
+            write!(f, " with BOM")?;
+        }
+        Ok(())
+    }
+}
+
+/// Contents of one side of a comparison
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileContent {
+    Text(String, TextEncoding),
+    Binary(Vec<u8>),
+}
+
+impl Default for FileContent {
+    fn default() -> Self {
+        FileContent::Text(String::new(), TextEncoding::default())
+    }
+}
+
+impl FileContent {
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            FileContent::Text(text, _) => Some(text),
+            FileContent::Binary(_) => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            FileContent::Text(text, _) => text.as_bytes(),
+            FileContent::Binary(bytes) => bytes,
+        }
+    }
+
+    pub fn encoding(&self) -> Option<TextEncoding> {
+        match self {
+            FileContent::Text(_, encoding) => Some(*encoding),
+            FileContent::Binary(_) => None,
+        }
+    }
+
+    pub fn is_binary(&self) -> bool {
+        matches!(self, FileContent::Binary(_))
+    }
+}
+
+pub fn read_file(path: &Path, options: &ReadOptions) -> Result<FileContent> {
+    let bytes = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    Ok(decode(bytes, options))
+}
+
+/// Decodes bytes to UTF-8 text. A byte order mark decides the encoding, then `--encoding`;
+/// without either, bytes are text when they are valid UTF-8. NUL bytes make a file binary
+/// whatever the encoding, except UTF-16 where every ASCII character has one.
+pub fn decode(bytes: Vec<u8>, options: &ReadOptions) -> FileContent {
+    if let Some((encoding, bom_len)) = encoding_rs::Encoding::for_bom(&bytes) {
+        let text = encoding.decode_without_bom_handling(&bytes[bom_len..]).0.into_owned();
+        return FileContent::Text(text, TextEncoding { encoding, bom: true });
+    }
+
+    let has_nul = bytes[..bytes.len().min(BINARY_SNIFF_LEN)].contains(&0);
+    if let Some(Encoding(encoding)) = options.encoding.filter(|&Encoding(encoding)| encoding != UTF_8) {
+        if !has_nul || encoding == UTF_16LE || encoding == UTF_16BE {
+            let text = encoding.decode_without_bom_handling(&bytes).0.into_owned();
+            return FileContent::Text(text, TextEncoding { encoding, bom: false });
+        }
+    }
+
+    let content = if has_nul {
+        FileContent::Binary(bytes)
+    } else {
+        match String::from_utf8(bytes) {
+            Ok(text) => FileContent::Text(text, TextEncoding::default()),
+            Err(err) => FileContent::Binary(err.into_bytes()),
+        }
+    };
+
+    match content {
+        FileContent::Binary(bytes) if options.hexdump => FileContent::Text(hexdump(&bytes), TextEncoding::default()),
+        content => content,
+    }
+}
+
+/// Formats bytes like `xxd`: an offset, 16 bytes in hex pairs, then the printable ASCII
+pub fn hexdump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (row, chunk) in bytes.chunks(HEXDUMP_WIDTH).enumerate() {
+        dump.push_str(&format!("{:08x}:", row * HEXDUMP_WIDTH));
+        for column in 0..HEXDUMP_WIDTH {
+            if column % 2 == 0 {
+                dump.push(' ');
+            }
+            match chunk.get(column) {
+                Some(byte) => dump.push_str(&format!("{:02x}", byte)),
+                None => dump.push_str("  "),
+            }
+        }
+
+        dump.push_str("  ");
+        dump.extend(chunk.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }));
+        dump.push('\n');
+    }
+    dump
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::WINDOWS_1252;
+    use super::*;
+
+    #[test]
+    fn nul_bytes_and_invalid_utf8_are_binary() {
+        let options = ReadOptions::default();
+        assert_eq!(decode(b"fn main() {}\n".to_vec(), &options), FileContent::Text("fn main() {}\n".to_string(), TextEncoding::default()));
+        assert!(decode(b"\x89PNG\r\n\x1a\n\0\0".to_vec(), &options).is_binary());
+        assert!(decode(b"caf\xe9".to_vec(), &options).is_binary());
+    }
+
+    #[test]
+    fn byte_order_marks_and_encoding_option_decode_to_utf8() {
+        let utf16 = decode(b"\xff\xfec\0a\0f\0\xe9\0\n\0".to_vec(), &ReadOptions::default());
+        assert_eq!(utf16.as_text(), Some("caf\u{e9}\n"));
+        assert_eq!(utf16.encoding().unwrap().to_string(), "UTF-16LE with BOM");
+
+        let utf8_bom = decode(b"\xef\xbb\xbfcaf\xc3\xa9\n".to_vec(), &ReadOptions::default());
+        assert_eq!(utf8_bom.as_text(), Some("caf\u{e9}\n"));
+        assert_eq!(utf8_bom.encoding().unwrap().to_string(), "UTF-8 with BOM");
+
+        let options = ReadOptions { encoding: Encoding::for_label("shift_jis"), ..Default::default() };
+        let shift_jis = decode(b"\x93\xfa\x96\x7b\n".to_vec(), &options);
+        assert_eq!(shift_jis.as_text(), Some("\u{65e5}\u{672c}\n"));
+        assert_eq!(shift_jis.encoding().unwrap().to_string(), "Shift_JIS");
+    }
+
+    #[test]
+    fn nul_bytes_stay_binary_with_a_single_byte_encoding() {
+        let options = ReadOptions { encoding: Some(Encoding(WINDOWS_1252)), ..Default::default() };
+        assert!(decode(b"\x89PNG\r\n\x1a\n\0\0".to_vec(), &options).is_binary());
+        assert_eq!(decode(b"caf\xe9\n".to_vec(), &options).as_text(), Some("caf\u{e9}\n"));
+
+        let hexdump = ReadOptions { hexdump: true, ..options };
+        assert_eq!(decode(b"\0\x01".to_vec(), &hexdump).as_text(), Some("00000000: 0001                                     ..\n"));
+
+        let utf16 = ReadOptions { encoding: Some(Encoding(UTF_16LE)), ..Default::default() };
+        assert_eq!(decode(b"h\0i\0".to_vec(), &utf16).as_text(), Some("hi"));
+    }
+
+    #[test]
+    fn hexdump_matches_xxd() {
+        assert_eq!(
+            hexdump(b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0\x03\0>"),
+            "00000000: 7f45 4c46 0201 0100 0000 0000 0000 0000  .ELF............\n\
+             00000010: 0300 3e                                  ..>\n",
+        );
+    }
+}
@@ -0,0 +1,42 @@
+//! Syntax-highlighted diffs for the terminal.
+//!
+//! `DiffProcessor` turns two texts, or an existing unified diff, into a `Patch`: the files
+//! of each commit, the hunks of each file with their parsed ranges, and the `DiffLine`s of
+//! each hunk. `Pager` renders a patch with syntax highlighting, word-level emphasis and
+//! optional side-by-side columns. `Comparison` reads two files or two directory trees from
+//! disk, pairing up renamed files, and diffs them into a patch. The `sabun` binary is a thin
+//! command line front end over this crate.
+//!
+//! ```
+//! use sabun::{DiffOptions, DiffProcessor, HunkRange, Pager};
+//!
+//! let processor = DiffProcessor::new().context_lines(1);
+//! let patch = processor.generate_diff(
+//!     "fn main() {\n    old();\n}\n",
+//!     "fn main() {\n    new();\n}\n",
+//!     &DiffOptions::new().old_name("a/main.rs").new_name("b/main.rs"),
+//! )?;
+//! let file = patch.files().next().unwrap();
+//! assert_eq!((file.added(), file.removed()), (1, 1));
+//! assert_eq!(file.hunks[0].new_range, HunkRange { start: 1, count: 3 });
+//!
+//! let rendered = Pager::new().line_numbers(true).render_to_string(&patch)?;
+//! assert!(rendered.contains("+++ b/main.rs"));
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+pub mod diff;
+pub mod pager;
+pub mod patch;
+pub mod syntax;
+mod colors;
+mod input;
+mod search;
+mod tree;
+
+pub use diff::{DiffAlgorithm, DiffLine, DiffLineType, DiffOptions, DiffProcessor, FileMeta, FileStatus, ParentChange, WhitespaceOptions};
+pub use input::{Encoding, ReadOptions};
+pub use pager::{MovedStyle, Pager, PagingMode};
+pub use patch::{Commit, FileDiff, Hunk, HunkRange, Patch};
+pub use syntax::{SyntaxHighlighter, SyntaxType};
+pub use tree::{CompareOptions, Comparison, RenameOptions, WalkOptions};
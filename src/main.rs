@@ -1,56 +1,338 @@
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::ffi::OsString;
 use std::io::{self, Read, IsTerminal};
-use std::fs;
-use anyhow::Result;
+use std::path::Path;
+use std::process;
+use anyhow::{bail, Result};
 
-mod diff;
-mod syntax;
-mod pager;
-mod colors;
+use sabun::diff;
+use sabun::diff::{DiffAlgorithm, DiffProcessor, WhitespaceOptions};
+use sabun::patch::Patch;
+use sabun::pager::{MovedStyle, Pager, PagingMode};
+use sabun::{CompareOptions, Comparison, Encoding, ReadOptions, RenameOptions, WalkOptions};
 
-use diff::DiffProcessor;
-use pager::Pager;
+fn main() {
+    // Like diff(1): 0 when the inputs are identical, 1 when they differ, 2 on trouble
+    match run() {
+        Ok(false) => process::exit(0),
+        Ok(true) => process::exit(1),
+        Err(err) => {
+            eprintln!("sabun: {:#}", err);
+            process::exit(2);
+        },
+    }
+}
 
-fn main() -> Result<()> {
+/// Runs sabun and returns whether the compared files differ. Diffs read from stdin count
+/// as identical, since sabun only displays them.
+fn run() -> Result<bool> {
     let matches = Command::new("sabun")
         .version("0.1.0")
         .about("A simple diff tool with syntax highlighting")
         .arg(
             Arg::new("file1")
-                .help("First file to compare")
+                .help("First file or directory to compare")
                 .index(1)
                 .required(false)
         )
         .arg(
             Arg::new("file2")
-                .help("Second file to compare")
+                .help("Second file or directory to compare")
                 .index(2)
                 .required(false)
         )
-        .get_matches();
+        .arg(
+            Arg::new("side-by-side")
+                .help("Show old and new versions in two columns")
+                .short('s')
+                .long("side-by-side")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("line-numbers")
+                .help("Show old and new line numbers in a gutter")
+                .short('n')
+                .long("line-numbers")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("paging")
+                .help("How to page output on a terminal [default: builtin, or external when SABUN_PAGER is set]")
+                .long("paging")
+                .value_name("MODE")
+                .value_parser(["builtin", "external", "never"])
+        )
+        .arg(
+            Arg::new("exclude")
+                .help("Skip files and directories matching PATTERN when comparing directories")
+                .short('x')
+                .long("exclude")
+                .value_name("PATTERN")
+                .action(ArgAction::Append)
+        )
+        .arg(
+            Arg::new("no-gitignore")
+                .help("Compare files ignored by .gitignore files inside the directories")
+                .long("no-gitignore")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("find-renames")
+                .help("Show removed and added files at least N% similar as renames when comparing directories")
+                .short('M')
+                .long("find-renames")
+                .value_name("N")
+                .value_parser(parse_percent)
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_value("50")
+                .default_missing_value("50")
+        )
+        .arg(
+            Arg::new("no-renames")
+                .help("Show renamed files as a removal and an addition")
+                .long("no-renames")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("find-copies")
+                .help("Show added files at least N% similar to a changed file as copies")
+                .short('C')
+                .long("find-copies")
+                .value_name("N")
+                .value_parser(parse_percent)
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("50")
+        )
+        .arg(
+            Arg::new("algorithm")
+                .help("Line diff algorithm for comparing files")
+                .long("algorithm")
+                .value_name("ALGORITHM")
+                .value_parser(["myers", "patience", "lcs"])
+                .default_value("myers")
+        )
+        .arg(
+            Arg::new("context")
+                .help("Show NUM lines of context around each change")
+                .short('U')
+                .long("context")
+                .value_name("NUM")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("3")
+        )
+        .arg(
+            Arg::new("function-context")
+                .help("Show the whole function around each change")
+                .short('W')
+                .long("function-context")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("ignore-all-space")
+                .help("Ignore all whitespace when comparing lines")
+                .short('w')
+                .long("ignore-all-space")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("ignore-space-change")
+                .help("Ignore changes in the amount of whitespace")
+                .short('b')
+                .long("ignore-space-change")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("ignore-blank-lines")
+                .help("Ignore changes that only add or remove blank lines")
+                .short('B')
+                .long("ignore-blank-lines")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("ignore-eol")
+                .help("Treat CRLF and LF line endings as equal")
+                .long("ignore-eol")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("color-moved")
+                .help("Colour blocks of lines moved elsewhere in the diff [default MODE: zebra]")
+                .long("color-moved")
+                .value_name("MODE")
+                .value_parser(["no", "plain", "zebra", "dimmed-zebra"])
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("zebra")
+        )
+        .arg(
+            Arg::new("hexdump")
+                .help("Diff binary files as hex dumps instead of summarizing them")
+                .long("hexdump")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("encoding")
+                .help("Encoding of files without a byte order mark, e.g. shift_jis, latin1 or utf-16le [default: utf-8]")
+                .long("encoding")
+                .value_name("ENCODING")
+        )
+        .arg(
+            Arg::new("brief")
+                .help("Only report whether the files differ")
+                .short('q')
+                .long("brief")
+                .visible_alias("quiet")
+                .action(ArgAction::SetTrue)
+        )
+        .get_matches_from(attach_thresholds(std::env::args_os()));
 
-    let processor = DiffProcessor::new();
+    let processor = build_processor(&matches);
     
     if let (Some(file1), Some(file2)) = (matches.get_one::<String>("file1"), matches.get_one::<String>("file2")) {
-        let content1 = fs::read_to_string(file1)?;
-        let content2 = fs::read_to_string(file2)?;
-        let diff_output = processor.generate_diff(&content1, &content2, Some(file1), Some(file2))?;
+        let options = CompareOptions {
+            read: read_options(&matches)?,
+            walk: walk_options(&matches),
+            renames: rename_options(&matches),
+        };
+        let comparison = Comparison::new(&processor, Path::new(file1), Path::new(file2), &options)?;
+        if matches.get_flag("brief") {
+            for line in comparison.brief_summary() {
+                println!("{}", line);
+            }
+            return Ok(comparison.differ());
+        }
         
-        let mut pager = Pager::new();
-        pager.display(&diff_output)?;
+        let mut patch = comparison.patch(&processor)?;
+        display(&matches, &mut patch)?;
+        Ok(comparison.differ())
+    } else if !io::stdin().is_terminal() {
+        // A diff can mix files in any encoding, so bytes that aren't UTF-8 are shown as U+FFFD
+        let mut stdin_content = Vec::new();
+        io::stdin().read_to_end(&mut stdin_content)?;
+        let mut patch = processor.parse_diff(&String::from_utf8_lossy(&stdin_content))?;
+        display(&matches, &mut patch)?;
+        Ok(false)
     } else {
-        if !io::stdin().is_terminal() {
-            let mut stdin_content = String::new();
-            io::stdin().read_to_string(&mut stdin_content)?;
-            let diff_output = processor.parse_diff(&stdin_content)?;
-            
-            let mut pager = Pager::new();
-            pager.display(&diff_output)?;
-        } else {
-            eprintln!("Usage: sabun <file1> <file2> or pipe diff to stdin");
-            std::process::exit(1);
+        bail!("usage: sabun <file1> <file2> or pipe diff to stdin");
+    }
+}
+
+fn read_options(matches: &ArgMatches) -> Result<ReadOptions> {
+    let encoding = match matches.get_one::<String>("encoding") {
+        Some(label) => match Encoding::for_label(label) {
+            Some(encoding) => Some(encoding),
+            None => bail!("unknown encoding: {}", label),
+        },
+        None => None,
+    };
+    Ok(ReadOptions { hexdump: matches.get_flag("hexdump"), encoding })
+}
+
+/// Rewrites the rename and copy thresholds git accepts, `-M60`, `-C60%` and `--find-renames 60`,
+/// to the `-M=60` form clap needs for an option whose value is optional. A number after the
+/// option is only taken as its threshold when no file of that name exists.
+fn attach_thresholds(args: impl IntoIterator<Item = OsString>) -> Vec<OsString> {
+    let is_threshold = |value: &str| {
+        let digits = value.strip_suffix('%').unwrap_or(value);
+        !digits.is_empty() && digits.bytes().all(|byte| byte.is_ascii_digit())
+    };
+    
+    let mut args = args.into_iter().peekable();
+    let mut attached = Vec::new();
+    while let Some(arg) = args.next() {
+        let text = arg.to_string_lossy().into_owned();
+        match text.as_str() {
+            "--" => {
+                attached.push(arg);
+                attached.extend(args);
+                break;
+            },
+            "-M" | "-C" | "--find-renames" | "--find-copies" => match args.peek().and_then(|next| next.to_str()) {
+                Some(next) if is_threshold(next) && !Path::new(next).exists() => {
+                    attached.push(format!("{}={}", text, next).into());
+                    args.next();
+                },
+                _ => attached.push(arg),
+            },
+            _ if (text.starts_with("-M") || text.starts_with("-C")) && is_threshold(&text[2..]) => {
+                attached.push(format!("{}={}", &text[..2], &text[2..]).into());
+            },
+            _ => attached.push(arg),
         }
     }
+    attached
+}
+
+/// A similarity threshold such as `60` or `60%`
+fn parse_percent(value: &str) -> Result<u8, String> {
+    match value.strip_suffix('%').unwrap_or(value).parse::<u8>() {
+        Ok(percent) if percent <= 100 => Ok(percent),
+        _ => Err(format!("{} is not a percentage from 0 to 100", value)),
+    }
+}
+
+fn rename_options(matches: &ArgMatches) -> RenameOptions {
+    RenameOptions {
+        renames: if matches.get_flag("no-renames") { None } else { matches.get_one::<u8>("find-renames").copied() },
+        copies: matches.get_one::<u8>("find-copies").copied(),
+    }
+}
+
+fn build_processor(matches: &ArgMatches) -> DiffProcessor {
+    let algorithm = match matches.get_one::<String>("algorithm").map(String::as_str) {
+        Some("patience") => DiffAlgorithm::Patience,
+        Some("lcs") => DiffAlgorithm::Lcs,
+        _ => DiffAlgorithm::Myers,
+    };
+    
+    DiffProcessor::new()
+        .algorithm(algorithm)
+        .context_lines(matches.get_one::<usize>("context").copied().unwrap_or(3))
+        .function_context(matches.get_flag("function-context"))
+        .whitespace(WhitespaceOptions {
+            ignore_all: matches.get_flag("ignore-all-space"),
+            ignore_amount: matches.get_flag("ignore-space-change"),
+            ignore_blank_lines: matches.get_flag("ignore-blank-lines"),
+            ignore_eol: matches.get_flag("ignore-eol"),
+        })
+}
+
+fn walk_options(matches: &ArgMatches) -> WalkOptions {
+    WalkOptions {
+        exclude: matches.get_many::<String>("exclude").into_iter().flatten().cloned().collect(),
+        gitignore: !matches.get_flag("no-gitignore"),
+    }
+}
+
+fn display(matches: &ArgMatches, patch: &mut Patch) -> Result<()> {
+    let moved_style = match matches.get_one::<String>("color-moved").map(String::as_str) {
+        Some("plain") => Some(MovedStyle::Plain),
+        Some("zebra") => Some(MovedStyle::Zebra),
+        Some("dimmed-zebra") => Some(MovedStyle::DimmedZebra),
+        _ => None,
+    };
+    
+    let mut pager = build_pager(matches);
+    if let Some(style) = moved_style {
+        diff::mark_moved_lines(patch);
+        pager = pager.moved_style(style);
+    }
+    pager.display(patch)
+}
+
+fn build_pager(matches: &ArgMatches) -> Pager {
+    let paging = match matches.get_one::<String>("paging").map(String::as_str) {
+        Some("external") => PagingMode::External,
+        Some("never") => PagingMode::Never,
+        Some(_) => PagingMode::Builtin,
+        None if std::env::var_os("SABUN_PAGER").is_some() => PagingMode::External,
+        None => PagingMode::Builtin,
+    };
     
-    Ok(())
-}
\ No newline at end of file
+    Pager::new()
+        .paging(paging)
+        .side_by_side(matches.get_flag("side-by-side"))
+        .line_numbers(matches.get_flag("line-numbers"))
+}
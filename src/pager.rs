@@ -1,67 +1,509 @@
-use std::io::Write;
+use std::io::{self, IsTerminal, Write};
+use std::ops::Range;
+use std::process::{Command, Stdio};
+use crossterm::cursor;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, DisableLineWrap, EnableLineWrap, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
 use termcolor::{BufferWriter, ColorChoice, ColorSpec, WriteColor};
-use anyhow::Result;
+use anyhow::{Context, Result};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
-use crate::diff::{DiffLine, DiffLineType};
+use crate::diff::{line_run, DiffLine, DiffLineType, FileMeta};
+use crate::patch::Patch;
+use crate::search::Search;
 use crate::syntax::SyntaxType;
 use crate::colors::DarkTheme;
 
+/// Narrowest column we render in side-by-side mode, however small the terminal is
+const MIN_COLUMN_WIDTH: usize = 20;
+const TAB_WIDTH: usize = 4;
+/// Tab stops of the terminal, for tabs written as they are in unified output
+const TERMINAL_TAB_STOP: usize = 8;
+
+/// How output is paged when stdout is a terminal
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum PagingMode {
+    /// Full-screen pager built into sabun, used when the diff is taller than the screen
+    Builtin,
+    /// Pager named by `SABUN_PAGER`, `GIT_PAGER` or `PAGER`, defaulting to `less -RFX`
+    External,
+    /// Always print directly to stdout
+    Never,
+}
+
+/// How lines that `mark_moved_lines` found moved are coloured
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum MovedStyle {
+    /// One colour for moved removed lines and one for moved added lines
+    Plain,
+    /// Alternate two shades between neighbouring blocks, so their boundaries show
+    Zebra,
+    /// Zebra with dimmed text, so moved code recedes and only real changes stand out
+    DimmedZebra,
+}
+
+/// Renders a `Patch` to the terminal, directly or through a pager
 pub struct Pager {
     buffer_writer: BufferWriter,
+    paging: PagingMode,
+    side_by_side: bool,
+    line_numbers: bool,
+    moved_style: MovedStyle,
+    // Digits needed for the largest line number of the diff being displayed
+    gutter_width: usize,
+    // Active search of the interactive pager, its matches are highlighted when rendering
+    search: Option<Search>,
+}
+
+impl Default for Pager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Pager {
     pub fn new() -> Self {
         let buffer_writer = BufferWriter::stdout(ColorChoice::Auto);
-        Self { buffer_writer }
+        Self {
+            buffer_writer,
+            paging: PagingMode::Builtin,
+            side_by_side: false,
+            line_numbers: false,
+            moved_style: MovedStyle::Zebra,
+            gutter_width: 0,
+            search: None,
+        }
+    }
+    
+    /// How `display` pages output that goes to a terminal.
+    pub fn paging(mut self, mode: PagingMode) -> Self {
+        self.paging = mode;
+        self
+    }
+    
+    /// Render removed lines in a left column next to their added counterparts on the right.
+    pub fn side_by_side(mut self, enabled: bool) -> Self {
+        self.side_by_side = enabled;
+        self
+    }
+    
+    /// Prefix content lines with a gutter holding their old and new line numbers.
+    pub fn line_numbers(mut self, enabled: bool) -> Self {
+        self.line_numbers = enabled;
+        self
+    }
+    
+    /// Colouring of moved lines, for diffs that went through `mark_moved_lines`.
+    pub fn moved_style(mut self, style: MovedStyle) -> Self {
+        self.moved_style = style;
+        self
+    }
+    
+    /// Writes `patch` to stdout, through the pager chosen with `paging` when stdout is a
+    /// terminal.
+    pub fn display(&mut self, patch: &Patch) -> Result<()> {
+        let diff_lines = patch.lines().collect::<Vec<_>>();
+        self.measure_gutter(&diff_lines);
+        let rendered = self.render(&diff_lines)?;
+        
+        if io::stdout().is_terminal() {
+            match self.paging {
+                PagingMode::Builtin => {
+                    // Only take over the screen when the diff would not fit on it anyway
+                    if let Ok((width, height)) = terminal::size() {
+                        if screen_rows(&rendered.buffer, width as usize).0.len() >= height as usize {
+                            return self.display_interactive(patch, &diff_lines, rendered);
+                        }
+                    }
+                },
+                PagingMode::External => {
+                    if let Some(command) = external_pager_command() {
+                        return self.display_external(&command, &rendered.buffer);
+                    }
+                },
+                PagingMode::Never => {},
+            }
+        }
+        
+        ignore_broken_pipe(self.buffer_writer.print(&rendered.buffer))
+    }
+    
+    /// Renders `patch` as it would be printed, with ANSI colours unless `NO_COLOR` or
+    /// `TERM=dumb` turns them off. Side-by-side columns fill the terminal, or 80 columns
+    /// without one.
+    pub fn render_to_string(&mut self, patch: &Patch) -> Result<String> {
+        let diff_lines = patch.lines().collect::<Vec<_>>();
+        self.measure_gutter(&diff_lines);
+        let rendered = self.render(&diff_lines)?;
+        Ok(String::from_utf8_lossy(rendered.buffer.as_slice()).into_owned())
+    }
+    
+    fn measure_gutter(&mut self, diff_lines: &[&DiffLine]) {
+        self.gutter_width = diff_lines.iter()
+            .flat_map(|line| [line.old_line_no, line.new_line_no])
+            .flatten()
+            .max()
+            .map_or(1, |max| max.to_string().len());
+    }
+    
+    /// Streams the rendered diff into `command` run through the shell, as git does with its pager.
+    fn display_external(&self, command: &str, buffer: &termcolor::Buffer) -> Result<()> {
+        let mut child = shell_command(command)
+            .env("LESS", std::env::var("LESS").unwrap_or_else(|_| "FRX".to_string()))
+            .stdin(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to run pager `{}`", command))?;
+        
+        if let Some(mut stdin) = child.stdin.take() {
+            // The user may quit the pager before reading everything
+            ignore_broken_pipe(stdin.write_all(buffer.as_slice()))?;
+        }
+        
+        child.wait()?;
+        Ok(())
     }
     
-    pub fn display(&mut self, diff_lines: &[DiffLine]) -> Result<()> {
-        self.display_direct(diff_lines)
+    fn render(&self, diff_lines: &[&DiffLine]) -> Result<Rendered> {
+        if self.side_by_side {
+            self.render_side_by_side(diff_lines)
+        } else {
+            self.render_unified(diff_lines)
+        }
     }
     
-    fn display_direct(&mut self, diff_lines: &[DiffLine]) -> Result<()> {
+    fn render_unified(&self, diff_lines: &[&DiffLine]) -> Result<Rendered> {
         let mut buffer = self.buffer_writer.buffer();
+        let mut line_rows = Vec::with_capacity(diff_lines.len());
+        let mut row = 0;
         
         for line in diff_lines {
+            line_rows.push(row);
+            let written = buffer.as_slice().len();
             self.write_line(&mut buffer, line)?;
+            row += count_rows(&buffer.as_slice()[written..]);
+        }
+        
+        Ok(Rendered { buffer, line_rows })
+    }
+    
+    fn render_side_by_side(&self, diff_lines: &[&DiffLine]) -> Result<Rendered> {
+        let mut buffer = self.buffer_writer.buffer();
+        let mut line_rows = vec![0; diff_lines.len()];
+        let mut row = 0;
+        let column_width = (terminal_width().saturating_sub(1) / 2).max(MIN_COLUMN_WIDTH);
+        
+        let mut idx = 0;
+        while idx < diff_lines.len() {
+            let written = buffer.as_slice().len();
+            match diff_lines[idx].line_type {
+                DiffLineType::Removed | DiffLineType::Added => {
+                    // Pair a run of removed lines with the run of added lines that follows it
+                    let removed = line_run(diff_lines, &mut idx, DiffLineType::Removed);
+                    let added = line_run(diff_lines, &mut idx, DiffLineType::Added);
+                    for pair in 0..removed.len().max(added.len()) {
+                        let written = buffer.as_slice().len();
+                        let (left, right) = (removed.get(pair).copied(), added.get(pair).copied());
+                        for line_idx in left.into_iter().chain(right) {
+                            line_rows[line_idx] = row;
+                        }
+                        self.write_side_by_side_row(&mut buffer, left.map(|i| diff_lines[i]), right.map(|i| diff_lines[i]), column_width)?;
+                        row += count_rows(&buffer.as_slice()[written..]);
+                    }
+                    continue;
+                },
+                DiffLineType::Context | DiffLineType::NoNewline => {
+                    let line = diff_lines[idx];
+                    self.write_side_by_side_row(&mut buffer, Some(line), Some(line), column_width)?;
+                },
+                _ => {
+                    self.write_line(&mut buffer, diff_lines[idx])?;
+                },
+            }
+            line_rows[idx] = row;
+            row += count_rows(&buffer.as_slice()[written..]);
+            idx += 1;
+        }
+        
+        Ok(Rendered { buffer, line_rows })
+    }
+    
+    /// Full-screen pager on the alternate screen. The terminal is restored even when drawing fails.
+    fn display_interactive(&mut self, patch: &Patch, diff_lines: &[&DiffLine], rendered: Rendered) -> Result<()> {
+        let mut stdout = io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(stdout, EnterAlternateScreen, DisableLineWrap, cursor::Hide)?;
+        
+        let result = self.run_interactive(&mut stdout, patch, diff_lines, rendered);
+        
+        execute!(stdout, cursor::Show, EnableLineWrap, LeaveAlternateScreen)?;
+        terminal::disable_raw_mode()?;
+        result
+    }
+    
+    fn run_interactive(&mut self, stdout: &mut io::Stdout, patch: &Patch, diff_lines: &[&DiffLine], rendered: Rendered) -> Result<()> {
+        let mut screen = Screen::new(patch, diff_lines, rendered, self.search.as_ref(), terminal_width());
+        let mut top = 0;
+        // Search being typed at the prompt, with the position to return to if it is cancelled
+        let mut prompt: Option<(Prompt, usize)> = None;
+        let mut message: Option<String> = None;
+        
+        loop {
+            let (width, height) = terminal::size()?;
+            // The last screen row is reserved for the status line
+            let page = (height as usize).saturating_sub(1).max(1);
+            let max_top = screen.rows.len().saturating_sub(page);
+            top = top.min(max_top);
+            
+            let status = match (&prompt, &message) {
+                (Some((prompt, _)), _) => prompt.to_string(),
+                (None, Some(message)) => format!(" {}", message),
+                (None, None) => {
+                    let bottom = (top + page).min(screen.rows.len());
+                    let percent = if screen.rows.is_empty() { 100 } else { bottom * 100 / screen.rows.len() };
+                    format!(" {}{}lines {}-{}/{} ({}%)  q:quit", screen.navigation.position(top), screen.match_position(top), top + 1, bottom, screen.rows.len(), percent)
+                },
+            };
+            self.draw_screen(stdout, &screen.rows, top, page, width, &status)?;
+            
+            let key = match event::read()? {
+                Event::Key(key) if key.kind != KeyEventKind::Release => key,
+                Event::Resize(..) => {
+                    // Rows wrap at the terminal width and side-by-side columns fill it
+                    screen = Screen::new(patch, diff_lines, self.render(diff_lines)?, self.search.as_ref(), terminal_width());
+                    continue;
+                },
+                _ => continue,
+            };
+            message = None;
+            
+            if let Some((mut current, origin)) = prompt.take() {
+                match key.code {
+                    KeyCode::Enter => {
+                        if screen.match_rows.is_empty() && !current.input.is_empty() {
+                            message = Some(format!("Pattern not found: {}", current.input));
+                        }
+                        continue;
+                    },
+                    KeyCode::Esc => {
+                        self.search = None;
+                        screen = Screen::new(patch, diff_lines, self.render(diff_lines)?, None, terminal_width());
+                        top = origin;
+                        continue;
+                    },
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break,
+                    KeyCode::Backspace => {
+                        current.input.pop();
+                    },
+                    KeyCode::Char(ch) => current.input.push(ch),
+                    _ => {},
+                }
+                
+                // Search as you type, starting from where the prompt was opened
+                self.search = Search::parse(&current.input, current.backward).ok().filter(|_| !current.input.is_empty());
+                screen = Screen::new(patch, diff_lines, self.render(diff_lines)?, self.search.as_ref(), terminal_width());
+                top = screen.find_match(origin, current.backward, true).unwrap_or(origin);
+                prompt = Some((current, origin));
+                continue;
+            }
+            
+            let half_page = (page / 2).max(1);
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => break,
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break,
+                KeyCode::Char('j') | KeyCode::Char('e') | KeyCode::Down | KeyCode::Enter => top += 1,
+                KeyCode::Char('k') | KeyCode::Char('y') | KeyCode::Up => top = top.saturating_sub(1),
+                KeyCode::Char('f') | KeyCode::Char(' ') | KeyCode::PageDown => top += page,
+                KeyCode::Char('b') | KeyCode::PageUp => top = top.saturating_sub(page),
+                KeyCode::Char('d') => top += half_page,
+                KeyCode::Char('u') => top = top.saturating_sub(half_page),
+                KeyCode::Char('g') | KeyCode::Home => top = 0,
+                KeyCode::Char('G') | KeyCode::End => top = screen.rows.len(),
+                KeyCode::Char(']') => top = next_after(&screen.navigation.hunk_rows, top).unwrap_or(top),
+                KeyCode::Char('[') => top = previous_before(&screen.navigation.hunk_rows, top).unwrap_or(top),
+                KeyCode::Char('}') => top = next_after(&screen.navigation.file_rows, top).unwrap_or(top),
+                KeyCode::Char('{') => top = previous_before(&screen.navigation.file_rows, top).unwrap_or(top),
+                KeyCode::Char(')') => top = next_after(&screen.navigation.commit_rows, top).unwrap_or(top),
+                KeyCode::Char('(') => top = previous_before(&screen.navigation.commit_rows, top).unwrap_or(top),
+                KeyCode::Char('/') => prompt = Some((Prompt { backward: false, input: String::new() }, top)),
+                KeyCode::Char('?') => prompt = Some((Prompt { backward: true, input: String::new() }, top)),
+                KeyCode::Char(key @ ('n' | 'N')) => {
+                    if let Some(search) = &self.search {
+                        // `N` searches against the direction the search was started in
+                        let backward = search.backward != (key == 'N');
+                        match screen.find_match(top, backward, false) {
+                            Some(row) => top = row,
+                            None => message = Some("No more matches".to_string()),
+                        }
+                    }
+                },
+                _ => {},
+            }
         }
         
-        self.buffer_writer.print(&buffer)?;
         Ok(())
     }
     
+    fn draw_screen(&self, stdout: &mut io::Stdout, rows: &[String], top: usize, page: usize, width: u16, status: &str) -> Result<()> {
+        for screen_row in 0..page {
+            queue!(stdout, cursor::MoveTo(0, screen_row as u16), Clear(ClearType::CurrentLine))?;
+            if let Some(row) = rows.get(top + screen_row) {
+                write!(stdout, "{}\x1b[0m", row)?;
+            }
+        }
+        
+        let status: String = status.chars().take(width as usize).collect();
+        queue!(
+            stdout,
+            cursor::MoveTo(0, page as u16),
+            Clear(ClearType::CurrentLine),
+            SetAttribute(Attribute::Reverse),
+            Print(status),
+            SetAttribute(Attribute::Reset),
+        )?;
+        stdout.flush()?;
+        Ok(())
+    }
+    
+    fn write_side_by_side_row(&self, buffer: &mut termcolor::Buffer, left: Option<&DiffLine>, right: Option<&DiffLine>, column_width: usize) -> Result<()> {
+        let left_rows = left.map(|line| self.column_rows(line, line.old_line_no, column_width)).unwrap_or_default();
+        let right_rows = right.map(|line| self.column_rows(line, line.new_line_no, column_width)).unwrap_or_default();
+        
+        for row in 0..left_rows.len().max(right_rows.len()) {
+            self.write_column(buffer, left_rows.get(row), column_width)?;
+            buffer.set_color(&DarkTheme::column_separator())?;
+            write!(buffer, "│")?;
+            self.write_column(buffer, right_rows.get(row), column_width)?;
+            buffer.reset()?;
+            writeln!(buffer)?;
+        }
+        Ok(())
+    }
+    
+    fn write_column(&self, buffer: &mut termcolor::Buffer, segments: Option<&Vec<(ColorSpec, String)>>, column_width: usize) -> Result<()> {
+        match segments {
+            Some(segments) => {
+                for (color_spec, text) in segments {
+                    buffer.set_color(color_spec)?;
+                    write!(buffer, "{}", text)?;
+                }
+            },
+            None => {
+                buffer.reset()?;
+                write!(buffer, "{}", " ".repeat(column_width))?;
+            },
+        }
+        Ok(())
+    }
+    
+    /// Lays out one line as a column: the line number and marker, then the highlighted content
+    /// wrapped to `column_width` and padded with the line background so every row is exactly that wide.
+    fn column_rows(&self, line: &DiffLine, line_no: Option<usize>, column_width: usize) -> Vec<Vec<(ColorSpec, String)>> {
+        let (marker, (base_color, emphasis_color)) = match line.line_type {
+            DiffLineType::Added => ('+', self.change_colors(line)),
+            DiffLineType::Removed => ('-', self.change_colors(line)),
+            DiffLineType::NoNewline => (' ', (DarkTheme::no_newline(), DarkTheme::no_newline())),
+            _ => (' ', (DarkTheme::context_line(), DarkTheme::context_line())),
+        };
+        let gutter_width = if self.line_numbers { self.gutter_width + 1 } else { 0 };
+        let content_width = column_width.saturating_sub(gutter_width + 1).max(1);
+        
+        let mut rows = Vec::new();
+        let mut row: Vec<(ColorSpec, String)> = Vec::new();
+        let mut row_width = 0;
+        for (color_spec, text) in self.styled_segments(line, &base_color, &emphasis_color) {
+            for ch in text.chars() {
+                let (ch, repeat) = if ch == '\t' { (' ', TAB_WIDTH) } else { (ch, 1) };
+                for _ in 0..repeat {
+                    let ch_width = ch.width().unwrap_or(0);
+                    if row_width + ch_width > content_width {
+                        rows.push(std::mem::take(&mut row));
+                        row_width = 0;
+                    }
+                    match row.last_mut() {
+                        Some((last_color, last_text)) if *last_color == color_spec => last_text.push(ch),
+                        _ => row.push((color_spec.clone(), ch.to_string())),
+                    }
+                    row_width += ch_width;
+                }
+            }
+        }
+        rows.push(row);
+        
+        for (row_idx, row) in rows.iter_mut().enumerate() {
+            let used: usize = row.iter().map(|(_, text)| text.width()).sum();
+            row.push((base_color.clone(), " ".repeat(content_width - used)));
+            let row_marker = if row_idx == 0 { marker } else { ' ' };
+            row.insert(0, (base_color.clone(), row_marker.to_string()));
+            if self.line_numbers {
+                let number = if row_idx == 0 { self.format_line_no(line_no) } else { self.format_line_no(None) };
+                row.insert(0, (DarkTheme::line_number(), format!("{} ", number)));
+            }
+        }
+        
+        rows
+    }
+    
     
     fn write_line(&self, buffer: &mut termcolor::Buffer, line: &DiffLine) -> Result<()> {
-        match line.line_type {
+        match &line.line_type {
             DiffLineType::FileHeader => {
-                buffer.set_color(&DarkTheme::file_header())?;
-                writeln!(buffer, "{}", line.content)?;
+                self.write_syntax_highlighted(buffer, line, &DarkTheme::file_header(), &DarkTheme::file_header())?;
+                writeln!(buffer)?;
             },
             DiffLineType::HunkHeader => {
-                buffer.set_color(&DarkTheme::hunk_header())?;
-                writeln!(buffer, "{}", line.content)?;
-            },
-            DiffLineType::Added => {
-                buffer.set_color(&DarkTheme::added_line())?;
-                write!(buffer, "+")?;
-                self.write_syntax_highlighted(buffer, &line.syntax_highlights, &DarkTheme::added_line())?;
-                // Clear to end of line with current background color
-                write!(buffer, "\x1b[K")?;
+                self.write_syntax_highlighted(buffer, line, &DarkTheme::hunk_header(), &DarkTheme::hunk_header())?;
+                writeln!(buffer)?;
+            },
+            DiffLineType::FileBanner(meta) => {
+                self.write_banner(buffer, line, meta)?;
+            },
+            DiffLineType::CommitHeader => {
+                // `commit <sha>` stands out, decorations such as `(HEAD -> main)` follow in plain colour
+                let sha_end = line.content.split_whitespace().take(2).map(|word| word.len() + 1).sum::<usize>() - 1;
+                self.write_two_tone(buffer, line, sha_end, &DarkTheme::commit_sha(), &DarkTheme::file_header())?;
+                writeln!(buffer)?;
+            },
+            DiffLineType::CommitMeta => {
+                let key_end = line.content.find(':').map_or(0, |colon| colon + 1);
+                self.write_two_tone(buffer, line, key_end, &DarkTheme::commit_key(), &DarkTheme::context_line())?;
+                writeln!(buffer)?;
+            },
+            DiffLineType::BinarySummary => {
+                self.write_syntax_highlighted(buffer, line, &DarkTheme::binary_summary(), &DarkTheme::binary_summary())?;
+                writeln!(buffer)?;
+            },
+            DiffLineType::EncodingNote => {
+                self.write_syntax_highlighted(buffer, line, &DarkTheme::encoding_note(), &DarkTheme::encoding_note())?;
+                writeln!(buffer)?;
+            },
+            DiffLineType::NoNewline => {
+                self.write_gutter(buffer, line)?;
+                self.write_syntax_highlighted(buffer, line, &DarkTheme::no_newline(), &DarkTheme::no_newline())?;
+                writeln!(buffer)?;
+            },
+            DiffLineType::CommitMessage => {
+                self.write_syntax_highlighted(buffer, line, &DarkTheme::commit_message(), &DarkTheme::commit_message())?;
                 writeln!(buffer)?;
             },
-            DiffLineType::Removed => {
-                buffer.set_color(&DarkTheme::removed_line())?;
-                write!(buffer, "-")?;
-                self.write_syntax_highlighted(buffer, &line.syntax_highlights, &DarkTheme::removed_line())?;
-                // Clear to end of line with current background color
+            DiffLineType::Added | DiffLineType::Removed => {
+                let marker = if line.line_type == DiffLineType::Added { '+' } else { '-' };
+                let (base_color, emphasis_color) = self.change_colors(line);
+                self.write_gutter(buffer, line)?;
+                self.write_marker(buffer, line, marker, &base_color)?;
+                self.write_syntax_highlighted(buffer, line, &base_color, &emphasis_color)?;
+                // Clear to end of line with the line background color
+                buffer.set_color(&base_color)?;
                 write!(buffer, "\x1b[K")?;
                 writeln!(buffer)?;
             },
             DiffLineType::Context => {
-                buffer.set_color(&DarkTheme::context_line())?;
-                write!(buffer, " ")?;
-                self.write_syntax_highlighted(buffer, &line.syntax_highlights, &DarkTheme::context_line())?;
+                self.write_gutter(buffer, line)?;
+                self.write_marker(buffer, line, ' ', &DarkTheme::context_line())?;
+                self.write_syntax_highlighted(buffer, line, &DarkTheme::context_line(), &DarkTheme::context_line())?;
                 writeln!(buffer)?;
             },
         }
@@ -71,34 +513,443 @@ impl Pager {
     }
     
     
-    fn write_syntax_highlighted(&self, buffer: &mut termcolor::Buffer, highlights: &[(SyntaxType, String)], base_color: &ColorSpec) -> Result<()> {
-        for (syntax_type, text) in highlights {
-            let mut color_spec = base_color.clone();
-            
-            match syntax_type {
-                SyntaxType::Keyword => {
-                    color_spec.set_fg(Some(termcolor::Color::Blue)).set_bold(true);
-                },
-                SyntaxType::String => {
-                    color_spec.set_fg(Some(termcolor::Color::Green)).set_intense(true);
-                },
-                SyntaxType::Comment => {
-                    color_spec.set_fg(Some(termcolor::Color::Green)).set_dimmed(true);
-                },
-                SyntaxType::Number => {
-                    color_spec.set_fg(Some(termcolor::Color::Yellow)).set_intense(true);
-                },
-                SyntaxType::Type => {
-                    color_spec.set_fg(Some(termcolor::Color::Cyan)).set_intense(true);
-                },
-                SyntaxType::Normal => {
-                    // Keep base color
-                },
+    /// Line and emphasis colours of an added or removed line, which depend on whether it moved
+    fn change_colors(&self, line: &DiffLine) -> (ColorSpec, ColorSpec) {
+        let added = line.line_type == DiffLineType::Added;
+        let Some(block) = line.moved else {
+            return if added {
+                (DarkTheme::added_line(), DarkTheme::added_emphasis())
+            } else {
+                (DarkTheme::removed_line(), DarkTheme::removed_emphasis())
+            };
+        };
+        
+        let alternate = self.moved_style != MovedStyle::Plain && block % 2 == 1;
+        let mut color = if added { DarkTheme::moved_added(alternate) } else { DarkTheme::moved_removed(alternate) };
+        if self.moved_style == MovedStyle::DimmedZebra {
+            color.set_dimmed(true);
+        }
+        (color.clone(), color)
+    }
+    
+    /// Writes the `+`/`-`/` ` marker of a line, or for a combined diff one marker per merge
+    /// parent, each coloured by whether the line was added or removed against that parent
+    fn write_marker(&self, buffer: &mut termcolor::Buffer, line: &DiffLine, marker: char, base: &ColorSpec) -> Result<()> {
+        if line.parents.is_empty() {
+            buffer.set_color(base)?;
+            write!(buffer, "{}", marker)?;
+            return Ok(());
+        }
+        
+        for change in &line.parents {
+            buffer.set_color(&DarkTheme::parent_marker(change, base))?;
+            write!(buffer, "{}", change.marker())?;
+        }
+        Ok(())
+    }
+    
+    /// Writes the file status in its own colour followed by the paths, underlined by a rule
+    fn write_banner(&self, buffer: &mut termcolor::Buffer, line: &DiffLine, meta: &FileMeta) -> Result<()> {
+        let label_len = meta.status.label().len() + 1;
+        self.write_two_tone(buffer, line, label_len, &DarkTheme::file_status(&meta.status), &DarkTheme::file_header())?;
+        writeln!(buffer)?;
+        
+        buffer.set_color(&DarkTheme::column_separator())?;
+        writeln!(buffer, "{}", "─".repeat(terminal_width()))?;
+        Ok(())
+    }
+    
+    /// Writes the first `split` bytes of `line` in `head_color` and the rest in `tail_color`,
+    /// keeping search matches highlighted across both
+    fn write_two_tone(&self, buffer: &mut termcolor::Buffer, line: &DiffLine, split: usize, head_color: &ColorSpec, tail_color: &ColorSpec) -> Result<()> {
+        let mut offset = 0;
+        
+        for (color_spec, text) in self.styled_segments(line, tail_color, tail_color) {
+            if offset < split && color_spec == *tail_color {
+                let head_len = (split - offset).min(text.len());
+                buffer.set_color(head_color)?;
+                write!(buffer, "{}", &text[..head_len])?;
+                buffer.set_color(&color_spec)?;
+                write!(buffer, "{}", &text[head_len..])?;
+            } else {
+                buffer.set_color(&color_spec)?;
+                write!(buffer, "{}", text)?;
             }
-            
+            offset += text.len();
+        }
+        Ok(())
+    }
+    
+    fn write_gutter(&self, buffer: &mut termcolor::Buffer, line: &DiffLine) -> Result<()> {
+        if !self.line_numbers {
+            return Ok(());
+        }
+        
+        buffer.set_color(&DarkTheme::line_number())?;
+        write!(buffer, "{} │ {} │", self.format_line_no(line.old_line_no), self.format_line_no(line.new_line_no))?;
+        buffer.reset()?;
+        Ok(())
+    }
+    
+    fn format_line_no(&self, line_no: Option<usize>) -> String {
+        match line_no {
+            Some(line_no) => format!("{:>width$}", line_no, width = self.gutter_width),
+            None => " ".repeat(self.gutter_width),
+        }
+    }
+    
+    fn write_syntax_highlighted(&self, buffer: &mut termcolor::Buffer, line: &DiffLine, base_color: &ColorSpec, emphasis_color: &ColorSpec) -> Result<()> {
+        for (color_spec, text) in self.styled_segments(line, base_color, emphasis_color) {
             buffer.set_color(&color_spec)?;
             write!(buffer, "{}", text)?;
         }
         Ok(())
     }
-}
\ No newline at end of file
+    
+    /// Splits the highlighted content of `line` into coloured segments, layering word emphasis
+    /// and then search matches on top of the syntax colours.
+    fn styled_segments(&self, line: &DiffLine, base_color: &ColorSpec, emphasis_color: &ColorSpec) -> Vec<(ColorSpec, String)> {
+        let emphasis = &line.emphasis;
+        let search_matches = self.search.as_ref().map(|search| search.matches(line)).unwrap_or_default();
+        
+        let mut segments = Vec::new();
+        let mut offset = 0;
+        for (syntax_type, span) in &line.syntax_highlights {
+            // Split the span wherever an emphasized range or search match starts or ends
+            let mut cuts = vec![0, span.len()];
+            for range in emphasis.iter().chain(&search_matches) {
+                for pos in [range.start, range.end] {
+                    if pos > offset && pos < offset + span.len() && span.is_char_boundary(pos - offset) {
+                        cuts.push(pos - offset);
+                    }
+                }
+            }
+            cuts.sort_unstable();
+            cuts.dedup();
+            
+            for window in cuts.windows(2) {
+                let start = offset + window[0];
+                let text = span[window[0]..window[1]].to_string();
+                if search_matches.iter().any(|range| range.contains(&start)) {
+                    segments.push((DarkTheme::search_match(), text));
+                    continue;
+                }
+                let emphasized = emphasis.iter().any(|range| range.contains(&start));
+                let base = if emphasized { emphasis_color } else { base_color };
+                segments.push((self.syntax_color(syntax_type, base), text));
+            }
+            offset += span.len();
+        }
+        segments
+    }
+    
+    fn syntax_color(&self, syntax_type: &SyntaxType, base_color: &ColorSpec) -> ColorSpec {
+        let mut color_spec = base_color.clone();
+        
+        match syntax_type {
+            SyntaxType::Keyword => {
+                color_spec.set_fg(Some(termcolor::Color::Blue)).set_bold(true);
+            },
+            SyntaxType::String => {
+                color_spec.set_fg(Some(termcolor::Color::Green)).set_intense(true);
+            },
+            SyntaxType::Comment => {
+                color_spec.set_fg(Some(termcolor::Color::Green)).set_dimmed(true);
+            },
+            SyntaxType::Number => {
+                color_spec.set_fg(Some(termcolor::Color::Yellow)).set_intense(true);
+            },
+            SyntaxType::Type => {
+                color_spec.set_fg(Some(termcolor::Color::Cyan)).set_intense(true);
+            },
+            SyntaxType::Normal => {
+                // Keep base color
+            },
+        }
+        
+        color_spec
+    }
+}
+
+/// Picks the pager command from the environment. Returns `None` when paging is disabled
+/// (`cat` or an empty value) or when the pager is sabun itself, which would recurse.
+fn external_pager_command() -> Option<String> {
+    let command = ["SABUN_PAGER", "GIT_PAGER", "PAGER"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok())
+        .unwrap_or_else(|| "less -RFX".to_string());
+    
+    let program = command.split_whitespace().next()?;
+    let program_name = std::path::Path::new(program).file_stem()?.to_str()?;
+    if program_name == "cat" || program_name == "sabun" {
+        return None;
+    }
+    Some(command)
+}
+
+fn shell_command(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.args(["/C", command]);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.args(["-c", command]);
+        shell
+    }
+}
+
+/// Treats a closed output (`sabun a b | head`, quitting the pager early) as success
+fn ignore_broken_pipe(result: io::Result<()>) -> Result<()> {
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        other => Ok(other?),
+    }
+}
+
+fn terminal_width() -> usize {
+    crossterm::terminal::size()
+        .map(|(columns, _)| columns as usize)
+        .ok()
+        .or_else(|| std::env::var("COLUMNS").ok().and_then(|columns| columns.parse().ok()))
+        .filter(|&columns| columns > 0)
+        .unwrap_or(80)
+}
+
+/// Rendered output together with the screen row each diff line starts on
+struct Rendered {
+    buffer: termcolor::Buffer,
+    line_rows: Vec<usize>,
+}
+
+/// What the interactive pager shows: rendered rows wrapped to the terminal width and the rows
+/// it can jump to
+struct Screen {
+    rows: Vec<String>,
+    navigation: Navigation,
+    match_rows: Vec<usize>,
+}
+
+impl Screen {
+    fn new(patch: &Patch, diff_lines: &[&DiffLine], rendered: Rendered, search: Option<&Search>, width: usize) -> Self {
+        let (rows, starts) = screen_rows(&rendered.buffer, width);
+        let line_rows = rendered.line_rows.iter()
+            .map(|&row| starts.get(row).copied().unwrap_or(rows.len()))
+            .collect::<Vec<_>>();
+        
+        let mut match_rows: Vec<usize> = match search {
+            Some(search) => diff_lines.iter()
+                .zip(&line_rows)
+                .filter(|(line, _)| !search.matches(line).is_empty())
+                .map(|(_, &row)| row)
+                .collect(),
+            None => Vec::new(),
+        };
+        match_rows.dedup();
+        
+        Self {
+            rows,
+            navigation: Navigation::new(patch, &line_rows),
+            match_rows,
+        }
+    }
+    
+    /// Row of the next match after `top` (or before it when searching backward).
+    /// `inclusive` also accepts a match on `top` itself, as when a search is first typed.
+    fn find_match(&self, top: usize, backward: bool, inclusive: bool) -> Option<usize> {
+        match (backward, inclusive) {
+            (false, true) => self.match_rows.iter().copied().find(|&row| row >= top),
+            (false, false) => next_after(&self.match_rows, top),
+            (true, true) => self.match_rows.iter().copied().rev().find(|&row| row <= top),
+            (true, false) => previous_before(&self.match_rows, top),
+        }
+    }
+    
+    /// Describes the search state at `top`, e.g. "match 2/9  "
+    fn match_position(&self, top: usize) -> String {
+        if self.match_rows.is_empty() {
+            return String::new();
+        }
+        let current = self.match_rows.partition_point(|&row| row <= top);
+        format!("match {}/{}  ", current, self.match_rows.len())
+    }
+}
+
+/// The search prompt of the interactive pager
+struct Prompt {
+    backward: bool,
+    input: String,
+}
+
+impl std::fmt::Display for Prompt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", if self.backward { '?' } else { '/' }, self.input)
+    }
+}
+
+/// Screen rows where files and hunks start, used to jump between them in the pager
+struct Navigation {
+    commit_rows: Vec<usize>,
+    file_rows: Vec<usize>,
+    hunk_rows: Vec<usize>,
+}
+
+impl Navigation {
+    fn new(patch: &Patch, line_rows: &[usize]) -> Self {
+        let mut commit_rows = Vec::new();
+        let mut file_rows = Vec::new();
+        let mut hunk_rows = Vec::new();
+        
+        // Index of the first line of each commit, file and hunk in display order
+        let mut idx = 0;
+        let row_at = |idx: usize| line_rows.get(idx).copied();
+        for commit in &patch.commits {
+            if commit.sha.is_some() {
+                commit_rows.extend(row_at(idx));
+            }
+            idx += commit.header.len();
+            for file in &commit.files {
+                file_rows.extend(row_at(idx));
+                idx += file.header.len();
+                for hunk in &file.hunks {
+                    hunk_rows.extend(row_at(idx));
+                    idx += 1 + hunk.lines.len() + hunk.trailer.len();
+                }
+            }
+        }
+        
+        Self { commit_rows, file_rows, hunk_rows }
+    }
+    
+    /// Describes where `top` is, e.g. "commit 2/9, file 3/17, hunk 2/5  ". Files are counted
+    /// within the current commit and hunks within the current file.
+    fn position(&self, top: usize) -> String {
+        let mut position = String::new();
+        let mut files = &self.file_rows[..];
+        
+        if !self.commit_rows.is_empty() {
+            let (commit, range) = locate(&self.commit_rows, top);
+            position.push_str(&format!("commit {}/{}, ", commit, self.commit_rows.len()));
+            files = rows_within(files, range);
+        }
+        
+        if !files.is_empty() {
+            let (file, range) = locate(files, top);
+            let hunks = rows_within(&self.hunk_rows, range);
+            let hunk = hunks.partition_point(|&row| row <= top).max(1).min(hunks.len());
+            position.push_str(&format!("file {}/{}, hunk {}/{}, ", file, files.len(), hunk, hunks.len()));
+        }
+        
+        match position.strip_suffix(", ") {
+            Some(position) => format!("{}  ", position),
+            None => position,
+        }
+    }
+}
+
+/// Finds the section of `starts` that `top` is in, returning its 1-based number and its rows
+fn locate(starts: &[usize], top: usize) -> (usize, Range<usize>) {
+    let number = starts.partition_point(|&row| row <= top).max(1);
+    let end = starts.get(number).copied().unwrap_or(usize::MAX);
+    (number, starts[number - 1]..end)
+}
+
+fn rows_within(rows: &[usize], range: Range<usize>) -> &[usize] {
+    &rows[rows.partition_point(|&row| row < range.start)..rows.partition_point(|&row| row < range.end)]
+}
+
+fn next_after(rows: &[usize], top: usize) -> Option<usize> {
+    rows.iter().copied().find(|&row| row > top)
+}
+
+fn previous_before(rows: &[usize], top: usize) -> Option<usize> {
+    rows.iter().copied().rev().find(|&row| row < top)
+}
+
+fn count_rows(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|&&byte| byte == b'\n').count()
+}
+
+/// Splits rendered output into screen rows, keeping the colour escapes of each row
+fn split_rows(buffer: &termcolor::Buffer) -> Vec<String> {
+    String::from_utf8_lossy(buffer.as_slice())
+        .trim_end_matches("\x1b[0m")
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+/// Splits rendered output into screen rows of at most `width` columns, wrapping longer rows
+/// as the terminal would. Also returns the screen row each rendered row starts on.
+fn screen_rows(buffer: &termcolor::Buffer, width: usize) -> (Vec<String>, Vec<usize>) {
+    let mut rows = Vec::new();
+    let mut starts = Vec::new();
+    for row in split_rows(buffer) {
+        starts.push(rows.len());
+        rows.extend(wrap_row(&row, width));
+    }
+    (rows, starts)
+}
+
+/// Cuts a row into pieces of at most `width` columns. Pieces after the first start with the
+/// colour escapes still in effect, and tabs become spaces up to the next terminal tab stop.
+fn wrap_row(row: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut pieces = Vec::new();
+    let mut piece = String::new();
+    let mut column = 0;
+    // Escapes since the last reset, replayed at the start of each piece
+    let mut active = String::new();
+    
+    let mut chars = row.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\x1b' {
+            // A CSI sequence runs up to its final byte, e.g. "\x1b[38;5;15m"
+            let mut escape = String::from(ch);
+            escape.extend(chars.next());
+            for next in chars.by_ref() {
+                escape.push(next);
+                if ('\x40'..='\x7e').contains(&next) {
+                    break;
+                }
+            }
+            if escape == "\x1b[0m" {
+                active.clear();
+            } else {
+                active.push_str(&escape);
+            }
+            piece.push_str(&escape);
+            continue;
+        }
+        
+        let (ch, repeat) = if ch == '\t' { (' ', TERMINAL_TAB_STOP - column % TERMINAL_TAB_STOP) } else { (ch, 1) };
+        let ch_width = ch.width().unwrap_or(0);
+        for _ in 0..repeat {
+            if column + ch_width > width {
+                pieces.push(std::mem::replace(&mut piece, active.clone()));
+                column = 0;
+            }
+            piece.push(ch);
+            column += ch_width;
+        }
+    }
+    pieces.push(piece);
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn long_rows_wrap_with_their_colours() {
+        assert_eq!(wrap_row("abcdefg", 3), ["abc", "def", "g"]);
+        assert_eq!(wrap_row("abc", 3), ["abc"]);
+        assert_eq!(wrap_row("", 3), [""]);
+        // Escapes take no columns, and continuations restart the colour still in effect
+        assert_eq!(wrap_row("\x1b[31mabcd\x1b[0mef", 3), ["\x1b[31mabc", "\x1b[31md\x1b[0mef"]);
+        // Wide characters move to the next row rather than being split
+        assert_eq!(wrap_row("a日本", 4), ["a日", "本"]);
+        assert_eq!(wrap_row("a\tb", 4), ["a   ", "    ", "b"]);
+    }
+}
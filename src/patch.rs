@@ -0,0 +1,238 @@
+use std::fmt;
+
+use crate::diff::{DiffLine, DiffLineType, FileMeta};
+
+/// A whole diff as `DiffProcessor` produces it: the files of each commit, the hunks of each
+/// file and the lines of each hunk. `lines` walks it in display order.
+#[derive(Debug, Clone, Default)]
+pub struct Patch {
+    pub commits: Vec<Commit>,
+}
+
+/// The files of one commit in `git log -p` or `git show` output. A plain diff is a single
+/// `Commit` without a sha.
+#[derive(Debug, Clone, Default)]
+pub struct Commit {
+    /// Sha of the `commit <sha>` line, `None` for a plain diff
+    pub sha: Option<String>,
+    /// The `commit` line, author, date and message, or whatever precedes the first file of a
+    /// plain diff
+    pub header: Vec<DiffLine>,
+    pub files: Vec<FileDiff>,
+}
+
+/// One file of a diff
+#[derive(Debug, Clone)]
+pub struct FileDiff {
+    /// Paths and status of the file. The paths are empty when the diff never names the file,
+    /// as for a hunk on its own.
+    pub meta: FileMeta,
+    /// Lines shown before the hunks: the banner or `---`/`+++` lines, then notes such as a
+    /// binary summary
+    pub header: Vec<DiffLine>,
+    pub hunks: Vec<Hunk>,
+}
+
+/// One `@@` section of a file
+#[derive(Debug, Clone)]
+pub struct Hunk {
+    /// Range of the hunk in the old file, one per merge parent in a combined diff
+    pub old_ranges: Vec<HunkRange>,
+    pub new_range: HunkRange,
+    /// The `@@ -a,b +c,d @@` line
+    pub header: DiffLine,
+    /// Context, removed and added lines, with any `\ No newline at end of file` markers
+    pub lines: Vec<DiffLine>,
+    /// Lines after the hunk that belong to no hunk, such as the blank line before the next
+    /// commit or the `Only in` notes of `diff -r`
+    pub trailer: Vec<DiffLine>,
+}
+
+/// `start,count` of one side of a hunk header. A side without lines starts at the line
+/// before the hunk, as in `@@ -0,0 +1,3 @@`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HunkRange {
+    pub start: usize,
+    pub count: usize,
+}
+
+impl fmt::Display for HunkRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{}", self.start, self.count)
+    }
+}
+
+impl Patch {
+    /// A plain diff of `files`, outside of any commit
+    pub fn from_files(files: Vec<FileDiff>) -> Self {
+        Self { commits: vec![Commit { sha: None, header: Vec::new(), files }] }
+    }
+
+    /// Every line of the patch in display order, headers included
+    pub fn lines(&self) -> impl Iterator<Item = &DiffLine> {
+        self.commits.iter().flat_map(Commit::lines)
+    }
+
+    pub fn lines_mut(&mut self) -> impl Iterator<Item = &mut DiffLine> {
+        self.commits.iter_mut().flat_map(Commit::lines_mut)
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &FileDiff> {
+        self.commits.iter().flat_map(|commit| &commit.files)
+    }
+
+    pub fn files_mut(&mut self) -> impl Iterator<Item = &mut FileDiff> {
+        self.commits.iter_mut().flat_map(|commit| &mut commit.files)
+    }
+
+    pub fn hunks_mut(&mut self) -> impl Iterator<Item = &mut Hunk> {
+        self.files_mut().flat_map(|file| &mut file.hunks)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines().next().is_none()
+    }
+}
+
+impl Commit {
+    pub fn lines(&self) -> impl Iterator<Item = &DiffLine> {
+        self.header.iter().chain(self.files.iter().flat_map(FileDiff::lines))
+    }
+
+    pub fn lines_mut(&mut self) -> impl Iterator<Item = &mut DiffLine> {
+        self.header.iter_mut().chain(self.files.iter_mut().flat_map(FileDiff::lines_mut))
+    }
+}
+
+impl FileDiff {
+    pub fn new(meta: FileMeta) -> Self {
+        Self { meta, header: Vec::new(), hunks: Vec::new() }
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &DiffLine> {
+        self.header.iter().chain(self.hunks.iter().flat_map(Hunk::lines))
+    }
+
+    pub fn lines_mut(&mut self) -> impl Iterator<Item = &mut DiffLine> {
+        self.header.iter_mut().chain(self.hunks.iter_mut().flat_map(Hunk::lines_mut))
+    }
+
+    /// Number of added lines over all hunks
+    pub fn added(&self) -> usize {
+        self.hunks.iter().map(Hunk::added).sum()
+    }
+
+    /// Number of removed lines over all hunks
+    pub fn removed(&self) -> usize {
+        self.hunks.iter().map(Hunk::removed).sum()
+    }
+}
+
+impl Hunk {
+    /// The header followed by the content lines and the trailer
+    pub fn lines(&self) -> impl Iterator<Item = &DiffLine> {
+        std::iter::once(&self.header).chain(&self.lines).chain(&self.trailer)
+    }
+
+    pub fn lines_mut(&mut self) -> impl Iterator<Item = &mut DiffLine> {
+        std::iter::once(&mut self.header).chain(&mut self.lines).chain(&mut self.trailer)
+    }
+
+    pub fn added(&self) -> usize {
+        self.count(DiffLineType::Added)
+    }
+
+    pub fn removed(&self) -> usize {
+        self.count(DiffLineType::Removed)
+    }
+
+    fn count(&self, line_type: DiffLineType) -> usize {
+        self.lines.iter().filter(|line| line.line_type == line_type).count()
+    }
+}
+
+/// Assembles a `Patch` from lines arriving in display order, as `parse_diff` reads them
+#[derive(Default)]
+pub(crate) struct PatchBuilder {
+    patch: Patch,
+}
+
+impl PatchBuilder {
+    pub(crate) fn start_commit(&mut self, sha: &str) {
+        self.patch.commits.push(Commit { sha: Some(sha.to_string()), ..Default::default() });
+    }
+
+    pub(crate) fn start_file(&mut self, meta: FileMeta) {
+        self.commit_mut().files.push(FileDiff::new(meta));
+    }
+
+    /// Starts a hunk in the current file, or in an unnamed one when no file was started
+    pub(crate) fn start_hunk(&mut self, old_ranges: Vec<HunkRange>, new_range: HunkRange, header: DiffLine) {
+        if self.file_mut().is_none() {
+            self.start_file(FileMeta::new("", ""));
+        }
+        if let Some(file) = self.file_mut() {
+            file.hunks.push(Hunk { old_ranges, new_range, header, lines: Vec::new(), trailer: Vec::new() });
+        }
+    }
+
+    /// Appends a line to the current hunk, or before the first hunk to the header of the
+    /// current file or commit. Once the hunk has a trailer, the line stays in it to keep the
+    /// display order. Returns the line's index in the hunk's `lines` when it went there.
+    pub(crate) fn push(&mut self, line: DiffLine) -> Option<usize> {
+        let commit = self.commit_mut();
+        match commit.files.last_mut() {
+            Some(file) => match file.hunks.last_mut() {
+                Some(hunk) if hunk.trailer.is_empty() => {
+                    hunk.lines.push(line);
+                    Some(hunk.lines.len() - 1)
+                },
+                Some(hunk) => {
+                    hunk.trailer.push(line);
+                    None
+                },
+                None => {
+                    file.header.push(line);
+                    None
+                },
+            },
+            None => {
+                commit.header.push(line);
+                None
+            },
+        }
+    }
+
+    /// Appends a line that is not part of any hunk: to the header of the current file or commit
+    /// before the first hunk, to the trailer of the last hunk after it
+    pub(crate) fn push_loose(&mut self, line: DiffLine) {
+        let commit = self.commit_mut();
+        match commit.files.last_mut() {
+            Some(file) => match file.hunks.last_mut() {
+                Some(hunk) => hunk.trailer.push(line),
+                None => file.header.push(line),
+            },
+            None => commit.header.push(line),
+        }
+    }
+
+    pub(crate) fn file_mut(&mut self) -> Option<&mut FileDiff> {
+        self.patch.commits.last_mut()?.files.last_mut()
+    }
+
+    pub(crate) fn hunk_mut(&mut self) -> Option<&mut Hunk> {
+        self.file_mut()?.hunks.last_mut()
+    }
+
+    pub(crate) fn finish(self) -> Patch {
+        self.patch
+    }
+
+    fn commit_mut(&mut self) -> &mut Commit {
+        if self.patch.commits.is_empty() {
+            self.patch.commits.push(Commit::default());
+        }
+        let last = self.patch.commits.len() - 1;
+        &mut self.patch.commits[last]
+    }
+}
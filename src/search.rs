@@ -0,0 +1,121 @@
+use std::ops::Range;
+use regex::{Regex, RegexBuilder};
+
+use crate::diff::{DiffLine, DiffLineType};
+
+/// Which lines of the diff a search looks at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchScope {
+    All,
+    Added,
+    Removed,
+    FileNames,
+}
+
+#[derive(Debug, Clone)]
+pub struct Search {
+    regex: Regex,
+    scope: SearchScope,
+    pub backward: bool,
+}
+
+impl Search {
+    /// Parses a search typed at the pager prompt. A leading `+:`, `-:` or `f:` restricts the
+    /// search to added lines, removed lines or file names. The pattern is a regex that ignores
+    /// case unless it contains an uppercase letter.
+    pub fn parse(input: &str, backward: bool) -> Result<Self, regex::Error> {
+        let (scope, pattern) = if let Some(pattern) = input.strip_prefix("+:") {
+            (SearchScope::Added, pattern)
+        } else if let Some(pattern) = input.strip_prefix("-:") {
+            (SearchScope::Removed, pattern)
+        } else if let Some(pattern) = input.strip_prefix("f:") {
+            (SearchScope::FileNames, pattern)
+        } else {
+            (SearchScope::All, input)
+        };
+
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(!pattern.chars().any(char::is_uppercase))
+            .build()?;
+
+        Ok(Self { regex, scope, backward })
+    }
+
+    fn applies_to(&self, line_type: &DiffLineType) -> bool {
+        match self.scope {
+            SearchScope::All => true,
+            SearchScope::Added => *line_type == DiffLineType::Added,
+            SearchScope::Removed => *line_type == DiffLineType::Removed,
+            SearchScope::FileNames => matches!(line_type, DiffLineType::FileHeader | DiffLineType::FileBanner(_)),
+        }
+    }
+
+    /// Byte ranges of `line.content` matching the search, empty for lines outside its scope
+    pub fn matches(&self, line: &DiffLine) -> Vec<Range<usize>> {
+        if !self.applies_to(&line.line_type) {
+            return Vec::new();
+        }
+
+        self.regex.find_iter(&line.content)
+            .filter(|found| !found.is_empty())
+            .map(|found| found.range())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(line_type: DiffLineType, content: &str) -> DiffLine {
+        DiffLine {
+            line_type,
+            content: content.to_string(),
+            syntax_highlights: Vec::new(),
+            emphasis: Vec::new(),
+            old_line_no: None,
+            new_line_no: None,
+            parents: Vec::new(),
+            moved: None,
+        }
+    }
+
+    fn found<'a>(search: &Search, line: &'a DiffLine) -> Vec<&'a str> {
+        search.matches(line).into_iter().map(|range| &line.content[range]).collect()
+    }
+
+    #[test]
+    fn scope_prefixes_restrict_the_lines_searched() {
+        let added = line(DiffLineType::Added, "let total = 1;");
+        let removed = line(DiffLineType::Removed, "let total = 2;");
+        let header = line(DiffLineType::FileHeader, "+++ b/total.rs");
+
+        let everywhere = Search::parse("total", false).unwrap();
+        assert_eq!([&added, &removed, &header].map(|line| found(&everywhere, line).len()), [1, 1, 1]);
+
+        let added_only = Search::parse("+:total", false).unwrap();
+        assert_eq!(found(&added_only, &added), ["total"]);
+        assert!(found(&added_only, &removed).is_empty() && found(&added_only, &header).is_empty());
+
+        let removed_only = Search::parse("-:total", true).unwrap();
+        assert!(removed_only.backward);
+        assert_eq!(found(&removed_only, &removed), ["total"]);
+        assert!(found(&removed_only, &added).is_empty() && found(&removed_only, &header).is_empty());
+
+        let file_names = Search::parse("f:total", false).unwrap();
+        assert_eq!(found(&file_names, &header), ["total"]);
+        assert!(found(&file_names, &added).is_empty() && found(&file_names, &removed).is_empty());
+    }
+
+    #[test]
+    fn lowercase_patterns_ignore_case() {
+        let context = line(DiffLineType::Context, "Total total TOTAL");
+
+        assert_eq!(found(&Search::parse("total", false).unwrap(), &context), ["Total", "total", "TOTAL"]);
+        assert_eq!(found(&Search::parse("Total", false).unwrap(), &context), ["Total"]);
+        assert_eq!(found(&Search::parse("+:TOTAL", false).unwrap(), &line(DiffLineType::Added, "total TOTAL")), ["TOTAL"]);
+        // Empty matches would highlight nothing
+        assert!(found(&Search::parse("x*", false).unwrap(), &context).is_empty());
+        assert!(Search::parse("(", false).is_err());
+    }
+}
@@ -1,7 +1,14 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::path::Path;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use streaming_iterator::StreamingIterator;
+use std::ops::Range;
+use tree_sitter::{Language, Node, Parser, Query, QueryCursor, Tree};
 
+/// Highlighting class of a span of source text
 #[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
 pub enum SyntaxType {
     Keyword,
     String,
@@ -11,13 +18,45 @@ pub enum SyntaxType {
     Normal,
 }
 
-pub struct SyntaxHighlighter;
+/// Node kinds that count as a function for `--function-context`, across the supported grammars
+const FUNCTION_KINDS: &[&str] = &[
+    "function_item",
+    "function_definition",
+    "function_declaration",
+    "generator_function_declaration",
+    "method_definition",
+];
+
+struct LanguageConfig {
+    language: Language,
+    query: Query,
+}
+
+/// Tree-sitter based highlighter for the bundled grammars. The parser and the compiled
+/// queries sit behind `RefCell`s, so a highlighter is `Send` but not `Sync`.
+pub struct SyntaxHighlighter {
+    parser: RefCell<Parser>,
+    // Queries are compiled on first use; `None` marks a language we have no grammar for.
+    configs: RefCell<HashMap<String, Option<LanguageConfig>>>,
+}
+
+impl Default for SyntaxHighlighter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl SyntaxHighlighter {
-    pub fn new() -> Result<Self> {
-        Ok(Self)
+    /// Grammars are loaded on first use, so creating a highlighter can't fail
+    pub fn new() -> Self {
+        Self {
+            parser: RefCell::new(Parser::new()),
+            configs: RefCell::new(HashMap::new()),
+        }
     }
-    
+
+    /// Language name for a file name's extension, e.g. `rust` for `main.rs`. Unknown
+    /// extensions are returned as they are and highlight as plain text.
     pub fn detect_language<'a>(&self, filename: Option<&'a str>) -> Option<&'a str> {
         filename.and_then(|f| {
             Path::new(f)
@@ -25,119 +64,219 @@ impl SyntaxHighlighter {
                 .and_then(|ext| ext.to_str())
                 .map(|ext| match ext {
                     "rs" => "rust",
-                    "js" | "javascript" => "javascript", 
-                    "py" | "python" => "python",
-                    "c" => "c",
+                    "js" | "javascript" | "mjs" | "cjs" | "jsx" => "javascript",
+                    "ts" | "mts" | "cts" => "typescript",
+                    "tsx" => "tsx",
+                    "py" | "python" | "pyi" => "python",
+                    "c" | "h" => "c",
                     "json" => "json",
                     _ => ext,
                 })
         })
     }
-    
-    pub fn highlight_line(&self, line: &str, _language: Option<&str>) -> Result<Vec<(SyntaxType, String)>> {
-        Ok(self.basic_highlight(line))
+
+    /// Highlights a single line on its own, without the context of its document
+    pub fn highlight_line(&self, line: &str, language: Option<&str>) -> Result<Vec<(SyntaxType, String)>> {
+        let types = match language {
+            Some(language) => self.classify_bytes(line, language)?,
+            None => None,
+        };
+
+        Ok(match types {
+            Some(types) => Self::collect_spans(line, &types),
+            None => vec![(SyntaxType::Normal, line.to_string())],
+        })
     }
-    
-    fn basic_highlight(&self, line: &str) -> Vec<(SyntaxType, String)> {
+
+    /// Highlights `text` as one document, so constructs spanning several lines (block comments,
+    /// docstrings, raw strings) are coloured correctly. Returns one span list per line.
+    pub fn highlight_document(&self, text: &str, language: Option<&str>) -> Result<Vec<Vec<(SyntaxType, String)>>> {
+        let types = match language {
+            Some(language) => self.classify_bytes(text, language)?,
+            None => None,
+        };
+
         let mut result = Vec::new();
-        let mut current_word = String::new();
-        let mut in_string = false;
-        let mut string_char = None;
-        let mut in_comment = false;
-        let mut chars = line.chars().peekable();
-        
-        while let Some(ch) = chars.next() {
-            if in_comment {
-                let rest_of_line = std::iter::once(ch).chain(chars).collect::<String>();
-                result.push((SyntaxType::Comment, rest_of_line));
-                break;
+        let mut offset = 0;
+        for line in text.split_inclusive('\n') {
+            let content = line.trim_end_matches('\n');
+            result.push(match &types {
+                Some(types) => Self::collect_spans(content, &types[offset..offset + content.len()]),
+                None => vec![(SyntaxType::Normal, content.to_string())],
+            });
+            offset += line.len();
+        }
+
+        Ok(result)
+    }
+
+    /// Line ranges (0-based, end exclusive) of the functions and methods defined in `text`,
+    /// empty when no grammar is available for the language.
+    pub fn function_ranges(&self, text: &str, language: Option<&str>) -> Result<Vec<Range<usize>>> {
+        let tree = match language {
+            Some(language) => self.parse(text, language)?,
+            None => None,
+        };
+        let Some(tree) = tree else {
+            return Ok(Vec::new());
+        };
+
+        let mut ranges = Vec::new();
+        let mut cursor = tree.walk();
+        let mut visited_children = false;
+        loop {
+            let node = cursor.node();
+            if !visited_children && FUNCTION_KINDS.contains(&node.kind()) {
+                ranges.push(node.start_position().row..node.end_position().row + 1);
             }
-            
-            if in_string {
-                current_word.push(ch);
-                if Some(ch) == string_char && chars.peek() != Some(&'\\') {
-                    result.push((SyntaxType::String, current_word.clone()));
-                    current_word.clear();
-                    in_string = false;
-                    string_char = None;
-                }
+
+            if !visited_children && cursor.goto_first_child() {
                 continue;
             }
-            
-            match ch {
-                '"' | '\'' => {
-                    if !current_word.is_empty() {
-                        result.push((self.classify_word(&current_word), current_word.clone()));
-                        current_word.clear();
-                    }
-                    current_word.push(ch);
-                    in_string = true;
-                    string_char = Some(ch);
-                }
-                '/' if chars.peek() == Some(&'/') => {
-                    if !current_word.is_empty() {
-                        result.push((self.classify_word(&current_word), current_word.clone()));
-                        current_word.clear();
-                    }
-                    current_word.push(ch);
-                    current_word.push(chars.next().unwrap());
-                    in_comment = true;
-                }
-                '#' => {
-                    if !current_word.is_empty() {
-                        result.push((self.classify_word(&current_word), current_word.clone()));
-                        current_word.clear();
-                    }
-                    current_word.push(ch);
-                    in_comment = true;
-                }
-                c if c.is_whitespace() || c.is_ascii_punctuation() => {
-                    if !current_word.is_empty() {
-                        result.push((self.classify_word(&current_word), current_word.clone()));
-                        current_word.clear();
-                    }
-                    if !c.is_whitespace() {
-                        result.push((SyntaxType::Normal, c.to_string()));
-                    } else {
-                        result.push((SyntaxType::Normal, c.to_string()));
-                    }
-                }
-                _ => {
-                    current_word.push(ch);
-                }
+            if cursor.goto_next_sibling() {
+                visited_children = false;
+            } else if cursor.goto_parent() {
+                visited_children = true;
+            } else {
+                break;
             }
         }
-        
-        if !current_word.is_empty() {
-            if in_comment {
-                result.push((SyntaxType::Comment, current_word));
-            } else if in_string {
-                result.push((SyntaxType::String, current_word));
-            } else {
-                result.push((self.classify_word(&current_word), current_word));
+
+        Ok(ranges)
+    }
+
+    /// Parses `source` with the grammar for `language`, or returns `None` when there is none.
+    fn parse(&self, source: &str, language: &str) -> Result<Option<Tree>> {
+        let mut configs = self.configs.borrow_mut();
+        if !configs.contains_key(language) {
+            configs.insert(language.to_string(), Self::load_config(language)?);
+        }
+        let config = match configs.get(language) {
+            Some(Some(config)) => config,
+            _ => return Ok(None),
+        };
+
+        let mut parser = self.parser.borrow_mut();
+        parser.set_language(&config.language)?;
+        let tree = parser
+            .parse(source, None)
+            .ok_or_else(|| anyhow!("tree-sitter failed to parse {} source", language))?;
+        Ok(Some(tree))
+    }
+
+    /// Parses `source` with the grammar for `language` and returns the syntax type of every byte,
+    /// or `None` when no grammar is available for the language.
+    fn classify_bytes(&self, source: &str, language: &str) -> Result<Option<Vec<SyntaxType>>> {
+        let Some(tree) = self.parse(source, language)? else {
+            return Ok(None);
+        };
+        let configs = self.configs.borrow();
+        let Some(Some(config)) = configs.get(language) else {
+            return Ok(None);
+        };
+
+        let capture_names = config.query.capture_names();
+        let mut captures = Vec::new();
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(&config.query, tree.root_node(), source.as_bytes());
+        while let Some(query_match) = matches.next() {
+            for capture in query_match.captures {
+                let range = capture.node.byte_range();
+                let syntax_type = Self::classify_capture(capture_names[capture.index as usize], capture.node);
+                captures.push((range, query_match.pattern_index, syntax_type));
             }
         }
-        
-        if result.is_empty() {
-            result.push((SyntaxType::Normal, line.to_string()));
+
+        // Paint outer nodes before the nodes nested inside them, and for captures of the same
+        // node let the pattern that appears first in the query win, as tree-sitter-highlight does.
+        captures.sort_by(|(a_range, a_pattern, _), (b_range, b_pattern, _)| {
+            b_range.len().cmp(&a_range.len()).then(b_pattern.cmp(a_pattern))
+        });
+
+        let mut types = vec![SyntaxType::Normal; source.len()];
+        for (range, _, syntax_type) in captures {
+            for slot in &mut types[range] {
+                *slot = syntax_type.clone();
+            }
         }
-        
-        result
+
+        Ok(Some(types))
+    }
+
+    fn load_config(language: &str) -> Result<Option<LanguageConfig>> {
+        let (language, query_source): (Language, String) = match language {
+            "rust" => (
+                tree_sitter_rust::LANGUAGE.into(),
+                tree_sitter_rust::HIGHLIGHTS_QUERY.to_string(),
+            ),
+            "javascript" => (
+                tree_sitter_javascript::LANGUAGE.into(),
+                [tree_sitter_javascript::HIGHLIGHT_QUERY, tree_sitter_javascript::JSX_HIGHLIGHT_QUERY].join("\n"),
+            ),
+            "typescript" => (
+                tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+                [tree_sitter_typescript::HIGHLIGHTS_QUERY, tree_sitter_javascript::HIGHLIGHT_QUERY].join("\n"),
+            ),
+            "tsx" => (
+                tree_sitter_typescript::LANGUAGE_TSX.into(),
+                [
+                    tree_sitter_typescript::HIGHLIGHTS_QUERY,
+                    tree_sitter_javascript::HIGHLIGHT_QUERY,
+                    tree_sitter_javascript::JSX_HIGHLIGHT_QUERY,
+                ].join("\n"),
+            ),
+            "python" => (
+                tree_sitter_python::LANGUAGE.into(),
+                tree_sitter_python::HIGHLIGHTS_QUERY.to_string(),
+            ),
+            "c" => (
+                tree_sitter_c::LANGUAGE.into(),
+                tree_sitter_c::HIGHLIGHT_QUERY.to_string(),
+            ),
+            "json" => (
+                tree_sitter_json::LANGUAGE.into(),
+                tree_sitter_json::HIGHLIGHTS_QUERY.to_string(),
+            ),
+            _ => return Ok(None),
+        };
+
+        let query = Query::new(&language, &query_source)?;
+        Ok(Some(LanguageConfig { language, query }))
     }
-    
-    fn classify_word(&self, word: &str) -> SyntaxType {
-        match word {
-            "fn" | "let" | "mut" | "const" | "static" | "struct" | "enum" | "impl" | "trait" | 
-            "use" | "pub" | "mod" | "if" | "else" | "match" | "while" | "for" | "loop" | 
-            "break" | "continue" | "return" | "true" | "false" | "null" | "undefined" |
-            "function" | "var" | "class" | "def" | "import" | "from" | "as" => SyntaxType::Keyword,
-            
-            w if w.chars().all(|c| c.is_ascii_digit()) => SyntaxType::Number,
-            w if w.parse::<f64>().is_ok() => SyntaxType::Number,
-            
-            w if w.chars().next().map_or(false, |c| c.is_uppercase()) => SyntaxType::Type,
-            
+
+    fn classify_capture(name: &str, node: Node) -> SyntaxType {
+        let group = name.split('.').next().unwrap_or(name);
+        match group {
+            "keyword" => SyntaxType::Keyword,
+            "string" | "escape" => SyntaxType::String,
+            "comment" => SyntaxType::Comment,
+            "number" => SyntaxType::Number,
+            "type" | "constructor" => SyntaxType::Type,
+            // Grammars differ in how they tag literals: Rust marks numbers and booleans alike as
+            // `constant.builtin`, so tell them apart by node kind.
+            "constant" if name == "constant.builtin" => match node.kind() {
+                "integer_literal" | "float_literal" | "number_literal" | "integer" | "float" | "number" => SyntaxType::Number,
+                _ => SyntaxType::Keyword,
+            },
             _ => SyntaxType::Normal,
         }
     }
-}
\ No newline at end of file
+
+    fn collect_spans(text: &str, types: &[SyntaxType]) -> Vec<(SyntaxType, String)> {
+        let mut result: Vec<(SyntaxType, String)> = Vec::new();
+
+        for (idx, ch) in text.char_indices() {
+            let syntax_type = &types[idx];
+            match result.last_mut() {
+                Some((last_type, last_text)) if last_type == syntax_type => last_text.push(ch),
+                _ => result.push((syntax_type.clone(), ch.to_string())),
+            }
+        }
+
+        if result.is_empty() {
+            result.push((SyntaxType::Normal, text.to_string()));
+        }
+
+        result
+    }
+}
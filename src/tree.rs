@@ -0,0 +1,449 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Result};
+use ignore::overrides::OverrideBuilder;
+use ignore::WalkBuilder;
+use similar::TextDiff;
+
+use crate::diff::{banner_line, binary_summary_line, encoding_note_line, DiffLine, DiffOptions, DiffProcessor, FileMeta, FileStatus};
+use crate::input::{read_file, FileContent, ReadOptions};
+use crate::patch::{FileDiff, Patch};
+
+/// Which files of the two trees take part in a directory comparison
+#[derive(Debug, Clone, Default)]
+pub struct WalkOptions {
+    /// Glob patterns, in `.gitignore` syntax, of files and directories to leave out
+    pub exclude: Vec<String>,
+    /// Honour the `.gitignore` files found inside each tree
+    pub gitignore: bool,
+}
+
+/// A file present in one or both trees, by its path relative to the tree roots
+#[derive(Debug, Clone)]
+pub struct FilePair {
+    pub path: PathBuf,
+    pub old: Option<PathBuf>,
+    pub new: Option<PathBuf>,
+}
+
+/// Walks both trees and pairs their files by relative path, sorted by path. Files only found
+/// in the old tree were removed and files only found in the new tree were added.
+pub fn pair_files(old_root: &Path, new_root: &Path, options: &WalkOptions) -> Result<Vec<FilePair>> {
+    let mut pairs: BTreeMap<PathBuf, FilePair> = BTreeMap::new();
+
+    for path in walk_files(old_root, options)? {
+        let full_path = old_root.join(&path);
+        pairs.insert(path.clone(), FilePair { path, old: Some(full_path), new: None });
+    }
+    for path in walk_files(new_root, options)? {
+        let full_path = new_root.join(&path);
+        pairs.entry(path.clone())
+            .or_insert_with(|| FilePair { path, old: None, new: None })
+            .new = Some(full_path);
+    }
+
+    Ok(pairs.into_values().collect())
+}
+
+/// Lists the regular files under `root`, relative to it
+fn walk_files(root: &Path, options: &WalkOptions) -> Result<Vec<PathBuf>> {
+    let mut overrides = OverrideBuilder::new(root);
+    for pattern in &options.exclude {
+        overrides.add(&format!("!{}", pattern))?;
+    }
+
+    // Only the tree's own `.gitignore` files count, not those of the repository it sits in
+    // or the user's global excludes, so both sides are filtered the same way
+    let walker = WalkBuilder::new(root)
+        .hidden(false)
+        .parents(false)
+        .ignore(false)
+        .git_global(false)
+        .git_exclude(false)
+        .git_ignore(options.gitignore)
+        .require_git(false)
+        .overrides(overrides.build()?)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build();
+
+    let mut files = Vec::new();
+    for entry in walker {
+        let entry = entry?;
+        if entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+            if let Ok(path) = entry.path().strip_prefix(root) {
+                files.push(path.to_path_buf());
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// A file that differs between the two trees, with both versions read
+#[derive(Debug, Clone)]
+pub struct ChangedFile {
+    pub pair: FilePair,
+    pub meta: FileMeta,
+    pub old_content: FileContent,
+    pub new_content: FileContent,
+}
+
+/// Compares two directory trees, returning every changed, added or removed file. Text files
+/// that only differ in whitespace the processor ignores count as unchanged.
+pub fn changed_files(processor: &DiffProcessor, old_root: &Path, new_root: &Path, options: &WalkOptions, read_options: &ReadOptions) -> Result<Vec<ChangedFile>> {
+    let mut changes = Vec::new();
+
+    for pair in pair_files(old_root, new_root, options)? {
+        let old_content = read_side(pair.old.as_deref(), read_options)?;
+        let new_content = read_side(pair.new.as_deref(), read_options)?;
+        if pair.old.is_some() && pair.new.is_some() && !content_differs(processor, &old_content, &new_content) {
+            continue;
+        }
+
+        let path = pair.path.to_string_lossy().replace('\\', "/");
+        let mut meta = FileMeta::new(&path, &path);
+        meta.status = match (&pair.old, &pair.new) {
+            (None, _) => FileStatus::Added,
+            (_, None) => FileStatus::Deleted,
+            _ => FileStatus::Modified,
+        };
+        meta.binary = old_content.is_binary() || new_content.is_binary();
+        changes.push(ChangedFile { pair, meta, old_content, new_content });
+    }
+
+    Ok(changes)
+}
+
+/// Similarity thresholds, in percent, above which files are paired up as renames or copies
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RenameOptions {
+    /// Pair a removed file with an added one, `None` to report them separately
+    pub renames: Option<u8>,
+    /// Report an added file as a copy of a modified or removed file
+    pub copies: Option<u8>,
+}
+
+/// Folds removed and added files that are similar enough into renames, and marks added files
+/// similar to a modified or removed file as copies. Similarity is the share of matching lines,
+/// as computed by `similar`. The best scoring pairs are taken first, and each removed file is
+/// renamed at most once.
+pub fn detect_renames(changes: Vec<ChangedFile>, options: &RenameOptions) -> Vec<ChangedFile> {
+    let added = |change: &ChangedFile| change.meta.status == FileStatus::Added;
+    let removed = |change: &ChangedFile| change.meta.status == FileStatus::Deleted;
+    let mut changes = changes.into_iter().map(Some).collect::<Vec<_>>();
+
+    if let Some(threshold) = options.renames {
+        let mut candidates = Vec::new();
+        for (new_idx, new) in changes.iter().enumerate().filter(|(_, change)| change.as_ref().is_some_and(added)) {
+            for (old_idx, old) in changes.iter().enumerate().filter(|(_, change)| change.as_ref().is_some_and(removed)) {
+                let (old, new) = (old.as_ref().unwrap(), new.as_ref().unwrap());
+                if let Some(score) = similarity(&old.old_content, &new.new_content, threshold) {
+                    candidates.push((score, old_idx, new_idx));
+                }
+            }
+        }
+        candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.2.cmp(&b.2)));
+
+        for (score, old_idx, new_idx) in candidates {
+            if changes[old_idx].is_none() || changes[new_idx].as_ref().is_some_and(|change| !added(change)) {
+                continue;
+            }
+            let old = changes[old_idx].take().unwrap();
+            let new = changes[new_idx].as_mut().unwrap();
+            new.pair.old = old.pair.old;
+            new.old_content = old.old_content;
+            new.meta.old_path = old.meta.old_path;
+            new.meta.status = FileStatus::Renamed;
+            new.meta.similarity = Some(score);
+        }
+    }
+
+    if let Some(threshold) = options.copies {
+        let sources = changes.iter()
+            .flatten()
+            .filter(|change| matches!(change.meta.status, FileStatus::Modified | FileStatus::Deleted | FileStatus::Renamed))
+            .cloned()
+            .collect::<Vec<_>>();
+        for new in changes.iter_mut().flatten().filter(|change| added(change)) {
+            let best = sources.iter()
+                .filter_map(|source| Some((similarity(&source.old_content, &new.new_content, threshold)?, source)))
+                .max_by_key(|(score, _)| *score);
+            if let Some((score, source)) = best {
+                new.pair.old = source.pair.old.clone();
+                new.old_content = source.old_content.clone();
+                new.meta.old_path = source.meta.old_path.clone();
+                new.meta.status = FileStatus::Copied;
+                new.meta.similarity = Some(score);
+            }
+        }
+    }
+
+    changes.into_iter().flatten().collect()
+}
+
+/// Whether two files differ, under the processor's whitespace options when both are text.
+/// Text decoded from different encodings differs even when the characters are the same.
+pub fn content_differs(processor: &DiffProcessor, old: &FileContent, new: &FileContent) -> bool {
+    match (old.as_text(), new.as_text()) {
+        (Some(old_text), Some(new_text)) => old.encoding() != new.encoding() || processor.differs(old_text, new_text),
+        _ => old.as_bytes() != new.as_bytes(),
+    }
+}
+
+/// A note on the encodings of two text files when they were decoded from different ones
+pub fn encoding_note(processor: &DiffProcessor, old: &FileContent, new: &FileContent) -> Option<DiffLine> {
+    let (old_encoding, new_encoding) = (old.encoding()?, new.encoding()?);
+    if old_encoding == new_encoding {
+        return None;
+    }
+    let only_encoding = !processor.differs(old.as_text()?, new.as_text()?);
+    Some(encoding_note_line(&old_encoding.to_string(), &new_encoding.to_string(), only_encoding))
+}
+
+/// Percentage of lines two files share, or `None` when it is below `threshold`. Binary files
+/// are only similar to an identical copy.
+fn similarity(old: &FileContent, new: &FileContent, threshold: u8) -> Option<u8> {
+    match (old.as_text(), new.as_text()) {
+        (Some(old), Some(new)) => text_similarity(old, new, threshold),
+        _ => (!old.as_bytes().is_empty() && old.as_bytes() == new.as_bytes()).then_some(100),
+    }
+}
+
+fn text_similarity(old: &str, new: &str, threshold: u8) -> Option<u8> {
+    // Every empty file would look like a rename of every other one
+    if old.is_empty() || new.is_empty() {
+        return None;
+    }
+
+    // Cheap upper bound first: the ratio can't exceed what the shorter text could match
+    let (old_lines, new_lines) = (old.lines().count(), new.lines().count());
+    let best_case = 200 * old_lines.min(new_lines) / (old_lines + new_lines);
+    if best_case < threshold as usize {
+        return None;
+    }
+
+    let score = if old == new { 100 } else { (TextDiff::from_lines(old, new).ratio() * 100.0).floor() as u8 };
+    (score >= threshold).then_some(score)
+}
+
+/// Diffs every changed file, one after the other
+pub fn diff_trees(processor: &DiffProcessor, changes: &[ChangedFile]) -> Result<Patch> {
+    let mut files = Vec::new();
+    for change in changes {
+        match (change.old_content.as_text(), change.new_content.as_text()) {
+            (Some(old_content), Some(new_content)) => {
+                let mut file = processor.generate_file_diff(&change.meta, old_content, new_content)?;
+                if let Some(note) = encoding_note(processor, &change.old_content, &change.new_content) {
+                    file.header.push(note);
+                }
+                files.push(file);
+            },
+            _ => {
+                let side_name = |path: &Option<PathBuf>| path.as_ref().map_or("/dev/null".into(), |path| path.display().to_string());
+                let mut file = FileDiff::new(change.meta.clone());
+                file.header.push(banner_line(&change.meta));
+                file.header.push(binary_summary_line(
+                    &side_name(&change.pair.old),
+                    &side_name(&change.pair.new),
+                    change.old_content.as_bytes().len(),
+                    change.new_content.as_bytes().len(),
+                ));
+                files.push(file);
+            },
+        }
+    }
+    Ok(Patch::from_files(files))
+}
+
+/// The line `diff -qr` prints for a changed file: `Files a/x and b/x differ`, or
+/// `Only in a/dir: x` for a file on one side only
+pub fn brief_summary(change: &ChangedFile) -> String {
+    if let (Some(old), Some(new), Some(similarity)) = (&change.pair.old, &change.pair.new, change.meta.similarity) {
+        return format!("File {} {} to {} ({}%)", old.display(), change.meta.status.label(), new.display(), similarity);
+    }
+
+    match (&change.pair.old, &change.pair.new) {
+        (Some(old), Some(new)) => format!("Files {} and {} differ", old.display(), new.display()),
+        (Some(path), None) | (None, Some(path)) => {
+            let dir = path.parent().map(Path::display);
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            match dir {
+                Some(dir) => format!("Only in {}: {}", dir, name),
+                None => format!("Only in .: {}", name),
+            }
+        },
+        (None, None) => String::new(),
+    }
+}
+
+/// How `Comparison` reads files and, for directories, which files it pairs up and how
+#[derive(Debug, Clone, Default)]
+pub struct CompareOptions {
+    pub read: ReadOptions,
+    pub walk: WalkOptions,
+    pub renames: RenameOptions,
+}
+
+/// The result of comparing two files or two directory trees, to be listed the way `diff -q`
+/// does or diffed into a `Patch`
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    changes: Vec<ChangedFile>,
+    /// Whether two files compared on their own differ, `None` for directories. Such a pair is
+    /// kept even when equal, as its `---`/`+++` headers are still shown.
+    files_differ: Option<bool>,
+}
+
+impl Comparison {
+    /// Compares two files, or two directories file by file. A file can't be compared with a
+    /// directory.
+    pub fn new(processor: &DiffProcessor, old: &Path, new: &Path, options: &CompareOptions) -> Result<Self> {
+        match (old.is_dir(), new.is_dir()) {
+            (true, true) => {
+                let changes = changed_files(processor, old, new, &options.walk, &options.read)?;
+                Ok(Comparison { changes: detect_renames(changes, &options.renames), files_differ: None })
+            },
+            (false, false) => {
+                let old_content = read_file(old, &options.read)?;
+                let new_content = read_file(new, &options.read)?;
+                let differ = content_differs(processor, &old_content, &new_content);
+                let mut meta = FileMeta::new(&old.display().to_string(), &new.display().to_string());
+                meta.binary = old_content.is_binary() || new_content.is_binary();
+                let pair = FilePair { path: new.to_path_buf(), old: Some(old.to_path_buf()), new: Some(new.to_path_buf()) };
+                Ok(Comparison { changes: vec![ChangedFile { pair, meta, old_content, new_content }], files_differ: Some(differ) })
+            },
+            _ => bail!("cannot compare a file with a directory: {} and {}", old.display(), new.display()),
+        }
+    }
+
+    pub fn differ(&self) -> bool {
+        self.files_differ.unwrap_or(!self.changes.is_empty())
+    }
+
+    /// One `diff -q` line per changed file
+    pub fn brief_summary(&self) -> Vec<String> {
+        if !self.differ() {
+            return Vec::new();
+        }
+        self.changes.iter().map(brief_summary).collect()
+    }
+
+    /// Diffs the changed files. Two files are shown under the names they were given, with
+    /// `---`/`+++` headers instead of a banner.
+    pub fn patch(&self, processor: &DiffProcessor) -> Result<Patch> {
+        let (Some(differ), [change]) = (self.files_differ, self.changes.as_slice()) else {
+            return diff_trees(processor, &self.changes);
+        };
+        let meta = &change.meta;
+        match (change.old_content.as_text(), change.new_content.as_text()) {
+            (Some(old_text), Some(new_text)) => {
+                let options = DiffOptions::new().old_name(&meta.old_path).new_name(&meta.new_path);
+                let mut patch = processor.generate_diff(old_text, new_text, &options)?;
+                if let (Some(note), Some(file)) = (encoding_note(processor, &change.old_content, &change.new_content), patch.files_mut().next()) {
+                    file.header.push(note);
+                }
+                Ok(patch)
+            },
+            _ if differ => {
+                let mut file = FileDiff::new(meta.clone());
+                file.header.push(binary_summary_line(
+                    &meta.old_path,
+                    &meta.new_path,
+                    change.old_content.as_bytes().len(),
+                    change.new_content.as_bytes().len(),
+                ));
+                Ok(Patch::from_files(vec![file]))
+            },
+            _ => Ok(Patch::default()),
+        }
+    }
+}
+
+/// Reads one side of a pair, a missing file being empty
+fn read_side(path: Option<&Path>, options: &ReadOptions) -> Result<FileContent> {
+    match path {
+        Some(path) => read_file(path, options),
+        None => Ok(FileContent::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::input::TextEncoding;
+
+    use super::*;
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn pairs_files_by_relative_path_and_skips_ignored_ones() {
+        let root = std::env::temp_dir().join(format!("sabun-tree-{}", std::process::id()));
+        let (old_root, new_root) = (root.join("old"), root.join("new"));
+        for tree in [&old_root, &new_root] {
+            write(tree, "src/lib.rs", "fn a() {}\n");
+            write(tree, ".gitignore", "out/\n");
+            write(tree, "out/generated.rs", "");
+            write(tree, "x.o", "");
+        }
+        write(&old_root, "gone.py", "");
+        write(&new_root, "src/util/new.py", "");
+
+        let options = WalkOptions { exclude: vec!["*.o".to_string()], gitignore: true };
+        let pairs = pair_files(&old_root, &new_root, &options).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        let summary = pairs.iter()
+            .map(|pair| (pair.path.to_string_lossy().replace('\\', "/"), pair.old.is_some(), pair.new.is_some()))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, [
+            (".gitignore".to_string(), true, true),
+            ("gone.py".to_string(), true, false),
+            ("src/lib.rs".to_string(), true, true),
+            ("src/util/new.py".to_string(), false, true),
+        ]);
+    }
+
+    fn change(status: FileStatus, path: &str, old_content: &str, new_content: &str) -> ChangedFile {
+        let mut meta = FileMeta::new(path, path);
+        meta.status = status.clone();
+        let full_path = |side: &str| Some(Path::new(side).join(path));
+        ChangedFile {
+            pair: FilePair {
+                path: PathBuf::from(path),
+                old: if status == FileStatus::Added { None } else { full_path("old") },
+                new: if status == FileStatus::Deleted { None } else { full_path("new") },
+            },
+            meta,
+            old_content: FileContent::Text(old_content.to_string(), TextEncoding::default()),
+            new_content: FileContent::Text(new_content.to_string(), TextEncoding::default()),
+        }
+    }
+
+    #[test]
+    fn similar_files_pair_up_as_renames_and_copies() {
+        let body = (1..=10).map(|n| format!("fn f{}() {{}}\n", n)).collect::<String>();
+        let changes = vec![
+            change(FileStatus::Modified, "src/kept.rs", &body, &format!("{}// more\n", body)),
+            change(FileStatus::Deleted, "src/a.rs", &body, ""),
+            change(FileStatus::Added, "src/copy.rs", "", &body),
+            change(FileStatus::Added, "src/util/a.rs", "", &body.replace("f7", "g7")),
+            change(FileStatus::Added, "unrelated.txt", "", "hello\n"),
+        ];
+
+        let options = RenameOptions { renames: Some(50), copies: Some(50) };
+        let summary = detect_renames(changes, &options).into_iter()
+            .map(|change| (change.meta.status, change.meta.old_path, change.meta.new_path, change.meta.similarity))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, [
+            (FileStatus::Modified, "src/kept.rs".to_string(), "src/kept.rs".to_string(), None),
+            // The exact match wins the removed file, the edited one is then a copy of it
+            (FileStatus::Renamed, "src/a.rs".to_string(), "src/copy.rs".to_string(), Some(100)),
+            (FileStatus::Copied, "src/a.rs".to_string(), "src/util/a.rs".to_string(), Some(90)),
+            (FileStatus::Added, "unrelated.txt".to_string(), "unrelated.txt".to_string(), None),
+        ]);
+    }
+}